@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::hardware;
+use crate::teensy;
+
+// the rate is counted over that much of the latest data
+const RATE_SECS: u64 = 10;
+
+// reads the teensy all the time in its own thread, every sample with the time it came
+// (the samples of one read are spread over the time since the read before)
+// the readings take their windows out of the buffer
+#[derive(Clone)]
+pub struct Acquisition {
+    shared: Arc<(Mutex<Buffer>, Condvar)>,
+}
+
+struct Buffer {
+    // oldest first, nothing older than buffer_secs
+    samples: VecDeque<(Instant, i32)>,
+    connected: bool,
+    // one more for every new port, a window on one port is whole
+    port: u64,
+    // why there is no port
+    error: String,
+    malformed: u64,
+    // the latest port read came back, with data or not
+    read_at: Instant,
+}
+
+impl Acquisition {
+    pub fn start(config: &config::Serial) -> Self {
+        let acquisition = Acquisition {
+            shared: Arc::new((
+                Mutex::new(Buffer {
+                    samples: VecDeque::new(),
+                    connected: false,
+                    port: 0,
+                    error: "not connected yet".to_string(),
+                    malformed: 0,
+                    read_at: Instant::now(),
+                }),
+                Condvar::new(),
+            )),
+        };
+
+        let shared = acquisition.shared.clone();
+        let config = config.clone();
+        thread::spawn(move || acquire(&shared, &config));
+
+        acquisition
+    }
+
+    fn buffer(&self) -> Option<MutexGuard<'_, Buffer>> {
+        self.shared.0.lock().ok()
+    }
+
+    // waits for a port up to timeout, Err says why there is none
+    pub fn port(&self, timeout: Duration) -> Result<u64, String> {
+        let (buffer, connected) = &*self.shared;
+        let buffer = buffer.lock().map_err(|e| e.to_string())?;
+        let (buffer, _) = connected
+            .wait_timeout_while(buffer, timeout, |buffer| !buffer.connected)
+            .map_err(|e| e.to_string())?;
+        if buffer.connected {
+            Ok(buffer.port)
+        } else {
+            Err(buffer.error.clone())
+        }
+    }
+
+    // the port is still the one the window started on
+    pub fn same_port(&self, port: u64) -> Result<(), String> {
+        match self.buffer() {
+            Some(buffer) if buffer.connected && buffer.port == port => Ok(()),
+            Some(buffer) if buffer.connected => Err("reconnected".to_string()),
+            Some(buffer) => Err(buffer.error.clone()),
+            None => Err("acquisition died".to_string()),
+        }
+    }
+
+    // waits up to timeout for a port read which came back at end or later,
+    // then the samples before end which were still in that read are in the buffer
+    pub fn settle(&self, end: Instant, timeout: Duration) {
+        let (buffer, changed) = &*self.shared;
+        if let Ok(buffer) = buffer.lock() {
+            let _ = changed.wait_timeout_while(buffer, timeout, |buffer| {
+                buffer.connected && buffer.read_at < end
+            });
+        }
+    }
+
+    // samples from start to end, end not included
+    pub fn window(&self, start: Instant, end: Instant) -> Vec<i32> {
+        self.buffer().map_or(vec![], |buffer| {
+            buffer
+                .samples
+                .iter()
+                .filter(|(at, _)| *at >= start && *at < end)
+                .map(|(_, value)| *value)
+                .collect()
+        })
+    }
+
+    // the latest secs as unix milliseconds and values, for drawing
+    pub fn latest(&self, secs: u64) -> Vec<(u64, i32)> {
+        let now = Instant::now();
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let since = now.checked_sub(Duration::from_secs(secs));
+        self.buffer().map_or(vec![], |buffer| {
+            buffer
+                .samples
+                .iter()
+                .filter(|(at, _)| since.is_none_or(|since| *at >= since))
+                .map(|(at, value)| {
+                    let ago = now.duration_since(*at).as_millis() as u64;
+                    (unix_now.saturating_sub(ago), *value)
+                })
+                .collect()
+        })
+    }
+
+    // samples per second over the latest data, None before there is any
+    pub fn rate(&self) -> Option<f32> {
+        let buffer = self.buffer()?;
+        let last = buffer.samples.back()?.0;
+        let since = last.checked_sub(Duration::from_secs(RATE_SECS));
+        let recent = buffer
+            .samples
+            .iter()
+            .filter(|(at, _)| since.is_none_or(|since| *at >= since))
+            .collect::<Vec<&(Instant, i32)>>();
+        let span = last.duration_since(recent.first()?.0).as_secs_f32();
+        if recent.len() < 2 || span <= 0.0 {
+            return None;
+        }
+        Some((recent.len() - 1) as f32 / span)
+    }
+
+    pub fn connected(&self) -> bool {
+        self.buffer().is_some_and(|buffer| buffer.connected)
+    }
+
+    pub fn malformed(&self) -> u64 {
+        self.buffer().map_or(0, |buffer| buffer.malformed)
+    }
+}
+
+// open, read until the port goes away, open again, forever
+fn acquire(shared: &(Mutex<Buffer>, Condvar), config: &config::Serial) {
+    let (buffer, changed) = shared;
+    let keep = Duration::from_secs(config.buffer_secs);
+    let mut serial_buf: Vec<u8> = vec![0; 512];
+    loop {
+        let mut port =
+            match hardware::find_port(config).and_then(|path| hardware::open_port(&path, config)) {
+                Ok(port) => port,
+                Err(error) => {
+                    if let Ok(mut buffer) = buffer.lock() {
+                        buffer.error = error;
+                    }
+                    thread::sleep(Duration::from_millis(500));
+                    continue;
+                }
+            };
+        eprintln!("Sensor connected");
+        if let Ok(mut buffer) = buffer.lock() {
+            buffer.connected = true;
+            buffer.port += 1;
+            changed.notify_all();
+        }
+
+        // a new port starts with a new frame
+        let mut parser = teensy::Parser::default();
+        // the bytes of a read came after the read before returned
+        let mut last = Instant::now();
+        let error = loop {
+            let read = port.read(serial_buf.as_mut_slice());
+            let now = Instant::now();
+            let since = std::mem::replace(&mut last, now);
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.read_at = now;
+                changed.notify_all();
+            }
+            match read {
+                // an unplugged tty reads nothing without waiting
+                Ok(0) => break "end of data".to_string(),
+                Ok(t) => {
+                    let values = parser.feed(&serial_buf[..t]);
+                    let count = values.len() as u32;
+                    let span = now - since;
+                    if let Ok(mut buffer) = buffer.lock() {
+                        buffer.samples.extend(
+                            values
+                                .into_iter()
+                                .zip(1..)
+                                .map(|(value, i)| (since + span * i / count, value)),
+                        );
+                        buffer.malformed += std::mem::take(&mut parser.malformed);
+                        while let Some((at, _)) = buffer.samples.front() {
+                            if now.duration_since(*at) <= keep {
+                                break;
+                            }
+                            buffer.samples.pop_front();
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => break e.to_string(),
+            }
+        };
+
+        eprintln!("Sensor lost: {}", error);
+        if let Ok(mut buffer) = buffer.lock() {
+            buffer.connected = false;
+            buffer.error = error;
+            changed.notify_all();
+        }
+    }
+}
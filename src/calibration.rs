@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::hardware::Hardware;
+
+// statistics of the idle pip data and the b/t we got from them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Calibration {
+    // unix seconds
+    pub timestamp: u64,
+    pub secs: u64,
+    pub samples: usize,
+    pub mean: f32,
+    pub median: f32,
+    pub std_dev: f32,
+    pub min: i32,
+    pub max: i32,
+    pub b: f32,
+    pub t: f32,
+}
+
+// nobody touches the plant meanwhile
+// records one second windows, stops early on cancel
+pub fn record(hw: &mut Hardware, config: &config::Calibration) -> Result<Calibration, String> {
+    let mut data: Vec<i32> = vec![];
+    let mut secs = 0;
+    while secs < config.secs && !hw.cancelled() {
+        data.extend(
+            hw.sensor
+                .read(1)
+                .map_err(|e| format!("sensor disconnected: {}", e.0))?,
+        );
+        secs += 1;
+    }
+    if hw.cancelled() {
+        return Err("calibration cancelled".to_string());
+    }
+    if data.len() < 2 {
+        return Err(format!(
+            "only {} samples in {}s, is the sensor there?",
+            data.len(),
+            secs
+        ));
+    }
+
+    let n = data.len() as f32;
+    let mean = data.iter().map(|&v| v as f32).sum::<f32>() / n;
+    let std_dev = (data.iter().map(|&v| (v as f32 - mean).powi(2)).sum::<f32>() / n).sqrt();
+
+    let mut sorted = data.clone();
+    sorted.sort_unstable();
+    let median = median(&sorted);
+
+    Ok(Calibration {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        secs,
+        samples: data.len(),
+        mean,
+        median,
+        std_dev,
+        min: sorted[0],
+        max: sorted[sorted.len() - 1],
+        // the median doesn't care about a few spikes
+        b: median,
+        t: config.sigmas * std_dev,
+    })
+}
+
+// middle of the sorted samples, not empty
+fn median(sorted: &[i32]) -> f32 {
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        // the sum of two i32 only fits in i64
+        (sorted[middle - 1] as i64 + sorted[middle] as i64) as f32 / 2.0
+    } else {
+        sorted[middle] as f32
+    }
+}
+
+// the profile becomes active, the history gets one more line
+pub fn save(calibration: &Calibration, config: &config::Calibration) -> Result<(), String> {
+    let profile = Path::new(&config.profile);
+    let contents = serde_json::to_string_pretty(calibration).map_err(|e| e.to_string())?;
+    let tmp = profile.with_extension("tmp");
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, profile))
+        .map_err(|e| format!("{}: {}", profile.display(), e))?;
+
+    let history = Path::new(&config.history);
+    let line = serde_json::to_string(calibration).map_err(|e| e.to_string())?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history)
+        .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()))
+        .map_err(|e| format!("{}: {}", history.display(), e))
+}
+
+// mock and replayed calibrations go to the temp dir, not into the installation
+pub fn mock(config: &config::Calibration) -> config::Calibration {
+    let tmp = std::env::temp_dir();
+    config::Calibration {
+        profile: tmp
+            .join("calibration.mock.profile")
+            .to_string_lossy()
+            .to_string(),
+        history: tmp
+            .join("calibration.mock.history")
+            .to_string_lossy()
+            .to_string(),
+        ..config.clone()
+    }
+}
+
+// None if nobody calibrated yet
+pub fn active(config: &config::Calibration) -> Result<Option<Calibration>, String> {
+    let profile = Path::new(&config.profile);
+    if !profile.exists() {
+        return Ok(None);
+    }
+
+    let contents =
+        fs::read_to_string(profile).map_err(|e| format!("{}: {}", profile.display(), e))?;
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| format!("{}: {}", profile.display(), e))
+}
+
+// oldest first
+pub fn history(config: &config::Calibration) -> Result<Vec<Calibration>, String> {
+    let path = Path::new(&config.history);
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut calibrations = vec![];
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        match serde_json::from_str(&line) {
+            Ok(calibration) => calibrations.push(calibration),
+            Err(error) => eprintln!("{}:{}: {}", path.display(), n + 1, error),
+        }
+    }
+
+    Ok(calibrations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_odd_and_even() {
+        assert_eq!(median(&[1, 5, 9]), 5.0);
+        assert_eq!(median(&[1, 4, 6, 9]), 5.0);
+        assert_eq!(median(&[7]), 7.0);
+        assert_eq!(median(&[i32::MAX, i32::MAX]), i32::MAX as f32);
+        assert_eq!(median(&[i32::MIN, i32::MIN]), i32::MIN as f32);
+    }
+}
@@ -0,0 +1,250 @@
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::Event;
+use crate::hardware::Hardware;
+use crate::protocol::{Format, Outcome, Reply};
+use crate::{calibration, journal, wires, Options};
+
+// ctrl-c stops the command the way cancel stops a reading
+fn stop_flag() -> Result<Arc<AtomicBool>, String> {
+    let flag = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM].iter() {
+        signal_hook::flag::register(*signal, flag.clone())
+            .map_err(|e| format!("can't watch signals: {:?}", e))?;
+    }
+    Ok(flag)
+}
+
+// core read-once: one reading, the result as json on the last line
+pub fn read_once(options: &Options) -> Result<(), String> {
+    let mut hw = options.hardware(stop_flag()?)?;
+    let (cast, trace) = wires::reading(&mut hw, &mut |event| {
+        if let Event::Line { line, value } = event {
+            println!("line {}: {}", line, value);
+        }
+    })
+    .map_err(|stopped| match stopped {
+        wires::Stopped::Cancelled => "reading cancelled".to_string(),
+        wires::Stopped::Disconnected(error) => format!("sensor disconnected: {}", error),
+        wires::Stopped::NoData(reason) => reason,
+    })?;
+
+    let fallback = trace.fallback.as_ref().map(|fallback| fallback.method);
+    journal::append(
+        Path::new(&options.config.journal.path),
+        &journal::Entry::new(&cast, &options.config.sensor, options.source(), trace),
+    );
+    print!(
+        "{}",
+        Reply::Result(Outcome::new(&cast, fallback)).encode(Format::Json)
+    );
+    Ok(())
+}
+
+// core test-leds: every line, then the li channel, a second each
+pub fn test_leds(options: &Options) -> Result<(), String> {
+    let mut hw = options.outputs(stop_flag()?);
+    if hw.leds.is_none() && !options.mock {
+        let leds = wires::build_controller(&options.config.leds, options.config.leds.brightness)
+            .ok_or("can't open the LEDs")?;
+        hw.leds = Some(Box::new(leds));
+    }
+
+    let colours = options.config.colours.clone();
+    for line_num in 1..=6 {
+        if hw.cancelled() {
+            break;
+        }
+        println!("line {}", line_num);
+        if let Some(controller) = hw.leds.as_deref_mut() {
+            wires::render_yang(line_num, controller, &colours.default);
+        }
+        hw.pause(1);
+        wires::blank(&mut hw);
+    }
+
+    if !hw.cancelled() {
+        println!("li");
+        if let Some(controller) = hw.leds.as_deref_mut() {
+            wires::drop_li_to_default(controller, &colours.li);
+        }
+        hw.pause(1);
+    }
+    wires::blank(&mut hw);
+
+    Ok(())
+}
+
+// core test-pin <n> --for <secs>: the watchdog still has the last word
+pub fn test_pin(options: &Options, pin: &str, secs: Option<&String>) -> Result<(), String> {
+    let pin = pin
+        .parse::<u8>()
+        .ok()
+        .filter(|pin| *pin <= 27)
+        .ok_or(format!("there is no gpio {} on rpi", pin))?;
+    let secs = match secs {
+        Some(secs) => secs
+            .parse::<u64>()
+            .map_err(|_| format!("bad --for: {}", secs))?,
+        None => 1,
+    };
+
+    let mut hw = options.outputs(stop_flag()?);
+    println!("pin {}: high for {}s", pin, secs);
+    hw.pins.set_high(pin);
+    hw.pause(secs);
+    hw.pins.set_low(pin);
+    println!("pin {}: low", pin);
+
+    Ok(())
+}
+
+// core play <effect>: fire, thunder, mountain or pin<n> of a scripted pin
+pub fn play(options: &Options, effect: &str) -> Result<(), String> {
+    let scripted = options
+        .config
+        .pins
+        .scripted
+        .iter()
+        .any(|pin| format!("pin{}", pin) == effect);
+    if !scripted && !["fire", "thunder", "mountain"].contains(&effect) {
+        return Err(format!(
+            "unknown effect \"{}\": fire, thunder, mountain or pin<n> of pins.scripted",
+            effect
+        ));
+    }
+
+    let mut hw = options.outputs(Arc::new(AtomicBool::new(false)));
+    hw.effects.run(effect)
+}
+
+// core sensor-dump [--for <secs>]: raw pip data in the capture format, until ctrl-c
+pub fn sensor_dump(options: &Options, secs: Option<&String>) -> Result<(), String> {
+    let secs = secs
+        .map(|secs| {
+            secs.parse::<u64>()
+                .map_err(|_| format!("bad --for: {}", secs))
+        })
+        .transpose()?;
+
+    let mut hw = options.hardware(stop_flag()?)?;
+    dump(&mut hw, secs, &mut io::stdout().lock())
+}
+
+// stdout is the capture, everything else goes to stderr
+fn dump(hw: &mut Hardware, secs: Option<u64>, out: &mut dyn Write) -> Result<(), String> {
+    writeln!(out, "# ioracle capture").map_err(|e| e.to_string())?;
+    let started = Instant::now();
+    let mut next = 0;
+    while !hw.cancelled() && secs.is_none_or(|secs| started.elapsed().as_secs() < secs) {
+        // one second windows, samples spread over the window
+        let start = next.max(started.elapsed().as_millis() as u64);
+        let data = hw
+            .sensor
+            .read(1)
+            .map_err(|e| format!("sensor disconnected: {}", e.0))?;
+        next = start + 1000;
+        let count = data.len() as u64;
+        for (i, value) in data.iter().enumerate() {
+            writeln!(out, "{} {}", start + i as u64 * 1000 / count, value)
+                .map_err(|e| e.to_string())?;
+        }
+
+        // the mock sensor doesn't wait for its window
+        let ahead = next.saturating_sub(started.elapsed().as_millis() as u64);
+        if hw.delays && ahead > 0 {
+            thread::sleep(Duration::from_millis(ahead));
+        }
+    }
+
+    Ok(())
+}
+
+// core calibrate [--for <secs>]: idle data in, active b/t out
+pub fn calibrate(options: &Options, secs: Option<&String>) -> Result<(), String> {
+    let mut config = if options.mock || options.replay.is_some() {
+        calibration::mock(&options.config.calibration)
+    } else {
+        options.config.calibration.clone()
+    };
+    if let Some(secs) = secs {
+        config.secs = secs
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or(format!("bad --for: {}", secs))?;
+    }
+
+    let mut hw = options.hardware(stop_flag()?)?;
+    println!("Calibrating for {}s, don't touch the plant", config.secs);
+    let calibration = calibration::record(&mut hw, &config)?;
+    println!(
+        "b = {} (was {}), t = {} (was {})",
+        calibration.b, options.config.sensor.b, calibration.t, options.config.sensor.t
+    );
+
+    calibration::save(&calibration, &config)?;
+    println!("Saved to {}", config.profile);
+    Ok(())
+}
+
+// core calibrate --history: every calibration, the active one marked
+pub fn calibrations(options: &Options) -> Result<(), String> {
+    let config = &options.config.calibration;
+    let active = calibration::active(config)?.map(|active| active.timestamp);
+    let history = calibration::history(config)?;
+    // the profile is a copy of the last save with its timestamp
+    let marked = history.iter().rposition(|c| Some(c.timestamp) == active);
+
+    println!("  timestamp   secs samples     mean   median  std_dev  min  max        b       t");
+    for (n, c) in history.iter().enumerate() {
+        println!(
+            "{} {} {:>5} {:>7} {:>8.1} {:>8.1} {:>8.2} {:>4} {:>4} {:>8.1} {:>7.2}",
+            if Some(n) == marked { "*" } else { " " },
+            c.timestamp,
+            c.secs,
+            c.samples,
+            c.mean,
+            c.median,
+            c.std_dev,
+            c.min,
+            c.max,
+            c.b,
+            c.t
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::hardware::Sensor;
+    use crate::mock::MockSensor;
+    use crate::replay::ReplaySensor;
+    use std::fs;
+
+    // what sensor-dump writes to stdout replays as it is
+    #[test]
+    fn dump_replays() {
+        let mut hw = Hardware::mock(Config::default(), Arc::new(AtomicBool::new(false)));
+        hw.sensor = Box::new(MockSensor::scripted(vec![vec![1, 2, 3], vec![4, 5]]));
+        let mut out = vec![];
+        dump(&mut hw, Some(2), &mut out).unwrap();
+
+        let path = std::env::temp_dir().join(format!("dump.{}.capture", std::process::id()));
+        fs::write(&path, &out).unwrap();
+        let mut replay = ReplaySensor::open(&path, 0.0).unwrap();
+        assert_eq!(replay.read(1).unwrap(), vec![1, 2, 3]);
+        assert_eq!(replay.read(1).unwrap(), vec![4, 5]);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,607 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// default place of the config file, missing file means defaults
+pub const CONFIG_FILE: &str = "/ioracle/ioracle.toml";
+
+// everything that differs between installations
+// defaults are the values of the first installation
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sockets: Sockets,
+    pub serial: Serial,
+    pub scripts: Scripts,
+    pub pumps: Pumps,
+    pub pins: Pins,
+    pub leds: Leds,
+    pub colours: Colours,
+    pub sensor: Sensor,
+    pub timing: Timing,
+    pub journal: Journal,
+    pub watchdog: Watchdog,
+    pub calibration: Calibration,
+    pub fallback: Fallback,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sockets {
+    pub send: String,
+    #[serde(rename = "return")]
+    pub return_: String,
+    pub outbox: String,
+    pub outbox_limit: usize,
+}
+
+impl Default for Sockets {
+    fn default() -> Self {
+        Sockets {
+            send: "/tmp/ioracle.send".to_string(),
+            return_: "/tmp/ioracle.return".to_string(),
+            outbox: "/tmp/ioracle.outbox".to_string(),
+            outbox_limit: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Serial {
+    // used when there is no vid, pid or serial_number to look for
+    pub port: String,
+    // usb ids of the teensy, like 0x16c0 and 0x0483
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub serial_number: Option<String>,
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: u8,
+    pub timeout_secs: u64,
+    // how long a window waits for a re-plugged teensy
+    pub reconnect_secs: u64,
+    // the latest samples kept in memory
+    pub buffer_secs: u64,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Serial {
+            port: "/dev/ttyACM0".to_string(),
+            vid: None,
+            pid: None,
+            serial_number: None,
+            baud_rate: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            timeout_secs: 1,
+            reconnect_secs: 5,
+            buffer_secs: 600,
+        }
+    }
+}
+
+impl Serial {
+    // look for the teensy on usb instead of the port
+    pub fn discovers(&self) -> bool {
+        self.vid.is_some() || self.pid.is_some() || self.serial_number.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scripts {
+    pub dir: String,
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Scripts {
+            dir: "/ioracle/scripts".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pumps {
+    pub usage_file: String,
+    // how many pump runs before a refill
+    pub refill_after: i32,
+}
+
+impl Default for Pumps {
+    fn default() -> Self {
+        Pumps {
+            usage_file: "/tmp/pumps.usage".to_string(),
+            refill_after: 6,
+        }
+    }
+}
+
+// gpio outputs of the trigram reactions
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pins {
+    pub heaven: u8,
+    pub cloud: u8,
+    pub wind: u8,
+    pub water: u8,
+    pub mountain: u8,
+    // these pins are run by /ioracle/scripts/pin<n>.sh
+    pub scripted: Vec<u8>,
+    // these pins run the pumps
+    pub pumps: Vec<u8>,
+    // these pins go low after every trigram
+    pub dropped: Vec<u8>,
+}
+
+impl Default for Pins {
+    fn default() -> Self {
+        Pins {
+            heaven: 5,
+            cloud: 8,
+            wind: 20,
+            water: 6,
+            mountain: 7,
+            scripted: vec![7, 8],
+            pumps: vec![6, 7, 8],
+            dropped: vec![5, 20, 6],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Leds {
+    pub leds_in_line: i32,
+    pub yao_pin: i32,
+    pub li_pin: i32,
+    pub freq: u32,
+    pub dma: i32,
+    pub brightness: u8,
+    pub resting_brightness: u8,
+}
+
+impl Default for Leds {
+    fn default() -> Self {
+        Leds {
+            leds_in_line: 144,
+            yao_pin: 12,
+            li_pin: 13,
+            freq: 800_000,
+            dma: 10,
+            brightness: 255,
+            resting_brightness: 50,
+        }
+    }
+}
+
+// colour string format: "rgb(108, 73, 211)"
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colours {
+    pub default: String,
+    pub li: String,
+    pub heaven: String,
+    pub cloud: String,
+    pub sun: String,
+    pub wind: String,
+    pub thunder: String,
+    pub water: String,
+    pub mountain: String,
+    pub earth: String,
+}
+
+impl Default for Colours {
+    fn default() -> Self {
+        Colours {
+            default: "rgb(51, 0, 180)".to_string(),
+            li: "rgb(230, 4, 211)".to_string(),
+            heaven: "rgb(224, 4, 235)".to_string(),
+            cloud: "rgb(255, 2, 14)".to_string(),
+            sun: "rgb(255, 109, 0)".to_string(),
+            wind: "rgb(121, 255, 0)".to_string(),
+            thunder: "rgb(255, 53, 6)".to_string(),
+            water: "rgb(38, 2, 255)".to_string(),
+            mountain: "rgb(14, 255, 232)".to_string(),
+            earth: "rgb(0, 0, 0)".to_string(),
+        }
+    }
+}
+
+// reading parameters: multiplier, bias, threshold and time windows
+// the line sees (value - b) * m, t is compared with that
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sensor {
+    // gain, no units, 2.0 makes a weak plant count twice
+    pub m: f32,
+    // per line position 1 to 6, like 4 = 2.0
+    pub line_m: BTreeMap<String, f32>,
+    pub b: f32,
+    pub t: f32,
+    pub line_secs: u64,
+    pub related_secs: u64,
+    // how a window becomes yin or yang
+    pub method: Method,
+    // hz, only for the spectral method
+    pub band: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    // more local maxima above t than minima below -t
+    #[default]
+    Extrema,
+    // mean above b
+    Mean,
+    // odd number of crossings of b
+    ZeroCrossing,
+    // the window goes up
+    Slope,
+    // more energy inside the band than outside
+    Spectral,
+    // odd number of odd samples
+    LsbParity,
+}
+
+impl Sensor {
+    // related lines share the gain of their position
+    pub fn m(&self, position: usize) -> f32 {
+        self.line_m
+            .get(&position.to_string())
+            .cloned()
+            .unwrap_or(self.m)
+    }
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Extrema => "extrema",
+            Method::Mean => "mean",
+            Method::ZeroCrossing => "zero_crossing",
+            Method::Slope => "slope",
+            Method::Spectral => "spectral",
+            Method::LsbParity => "lsb_parity",
+        }
+    }
+}
+
+impl Default for Sensor {
+    fn default() -> Self {
+        Sensor {
+            m: 1.0,
+            line_m: BTreeMap::new(),
+            b: 500.0,
+            t: 10.0,
+            line_secs: 2,
+            related_secs: 1,
+            method: Method::Extrema,
+            band: [0.5, 2.0],
+        }
+    }
+}
+
+// pauses, in seconds
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timing {
+    // after "read" before the first line
+    pub wait_for_user: u64,
+    // after the first and the second line of a trigram
+    pub after_line: u64,
+    // after the third line of a trigram
+    pub after_trigram: u64,
+    // before the earth trigram is drawn again
+    pub earth: u64,
+    // between the first and the second trigram
+    pub between_trigrams: u64,
+    // how long the result is shown
+    pub display: u64,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            wait_for_user: 4,
+            after_line: 3,
+            after_trigram: 2,
+            earth: 2,
+            between_trigrams: 3,
+            display: 5,
+        }
+    }
+}
+
+// every reading is appended here, one json object per line
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Journal {
+    pub path: String,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal {
+            path: "/ioracle/journal.jsonl".to_string(),
+        }
+    }
+}
+
+// longest time an output may stay high before it's forced low
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Watchdog {
+    pub max_on_secs: u64,
+    // per gpio, like 5 = 10
+    pub pins: BTreeMap<String, u64>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog {
+            max_on_secs: 20,
+            pins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Watchdog {
+    pub fn max_on(&self, pin: u8) -> u64 {
+        self.pins
+            .get(&pin.to_string())
+            .cloned()
+            .unwrap_or(self.max_on_secs)
+    }
+}
+
+// idle sensor data gives the bias and the threshold
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    // how long to record
+    pub secs: u64,
+    // threshold is this many standard deviations of the noise
+    pub sigmas: f32,
+    // the active b/t, it overrides sensor.b and sensor.t
+    pub profile: String,
+    // every calibration, one json object per line
+    pub history: String,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            secs: 60,
+            sigmas: 3.0,
+            profile: "/ioracle/calibration.json".to_string(),
+            history: "/ioracle/calibrations.jsonl".to_string(),
+        }
+    }
+}
+
+// a window without usable data, the rest of the reading goes the traditional way
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fallback {
+    pub method: FallbackMethod,
+    // fewer samples in a window is no data
+    pub min_samples: usize,
+    // max - min of a window below this is a flat line
+    pub min_range: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackMethod {
+    // the reading fails instead
+    Off,
+    // three coins
+    Coins,
+    // yarrow stalks odds
+    Yarrow,
+}
+
+impl FallbackMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FallbackMethod::Off => "off",
+            FallbackMethod::Coins => "coins",
+            FallbackMethod::Yarrow => "yarrow",
+        }
+    }
+}
+
+impl Default for Fallback {
+    fn default() -> Self {
+        Fallback {
+            method: FallbackMethod::Coins,
+            min_samples: 3,
+            min_range: 1,
+        }
+    }
+}
+
+impl Config {
+    // missing file is fine only for the default path
+    pub fn load(path: &Path, required: bool) -> Result<Self, String> {
+        if !required && !path.exists() {
+            eprintln!("No config at {}, using defaults", path.display());
+            return Ok(Config::default());
+        }
+
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Config =
+            toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(config)
+    }
+
+    // errors name the bad key
+    pub fn validate(&self) -> Result<(), String> {
+        let colours = [
+            ("colours.default", &self.colours.default),
+            ("colours.li", &self.colours.li),
+            ("colours.heaven", &self.colours.heaven),
+            ("colours.cloud", &self.colours.cloud),
+            ("colours.sun", &self.colours.sun),
+            ("colours.wind", &self.colours.wind),
+            ("colours.thunder", &self.colours.thunder),
+            ("colours.water", &self.colours.water),
+            ("colours.mountain", &self.colours.mountain),
+            ("colours.earth", &self.colours.earth),
+        ];
+        for (key, colour) in colours.iter() {
+            if !valid_colour(colour) {
+                return Err(format!(
+                    "{}: \"{}\" is not like \"rgb(51, 0, 180)\"",
+                    key, colour
+                ));
+            }
+        }
+
+        let pins = [
+            ("pins.heaven", self.pins.heaven),
+            ("pins.cloud", self.pins.cloud),
+            ("pins.wind", self.pins.wind),
+            ("pins.water", self.pins.water),
+            ("pins.mountain", self.pins.mountain),
+        ];
+        let lists = [
+            ("pins.scripted", &self.pins.scripted),
+            ("pins.pumps", &self.pins.pumps),
+            ("pins.dropped", &self.pins.dropped),
+        ];
+        let listed = lists
+            .iter()
+            .flat_map(|(key, list)| list.iter().map(move |pin| (*key, *pin)));
+        for (key, pin) in pins.iter().cloned().chain(listed) {
+            if pin > 27 {
+                return Err(format!("{}: there is no gpio {} on rpi", key, pin));
+            }
+        }
+
+        if self.leds.leds_in_line < 3 {
+            return Err("leds.leds_in_line: must be at least 3".to_string());
+        }
+        if self.serial.baud_rate == 0 {
+            return Err("serial.baud_rate: must be greater than 0".to_string());
+        }
+        if self.serial.timeout_secs == 0 {
+            return Err("serial.timeout_secs: must be greater than 0".to_string());
+        }
+        if self.serial.buffer_secs == 0 {
+            return Err("serial.buffer_secs: must be greater than 0".to_string());
+        }
+        if !(5..=8).contains(&self.serial.data_bits) {
+            return Err("serial.data_bits: must be 5, 6, 7 or 8".to_string());
+        }
+        if !(1..=2).contains(&self.serial.stop_bits) {
+            return Err("serial.stop_bits: must be 1 or 2".to_string());
+        }
+        if self.sensor.line_secs == 0 {
+            return Err("sensor.line_secs: must be greater than 0".to_string());
+        }
+        if self.sensor.related_secs == 0 {
+            return Err("sensor.related_secs: must be greater than 0".to_string());
+        }
+        if !self.sensor.m.is_finite() || !self.sensor.b.is_finite() || !self.sensor.t.is_finite() {
+            return Err("sensor: m, b and t must be numbers".to_string());
+        }
+        if self.sensor.m <= 0.0 {
+            return Err("sensor.m: must be greater than 0".to_string());
+        }
+        for (position, m) in &self.sensor.line_m {
+            match position.parse::<usize>() {
+                Ok(1..=6) => {}
+                _ => {
+                    return Err(format!(
+                        "sensor.line_m.{}: lines are 1 to 6, 1 is the bottom",
+                        position
+                    ))
+                }
+            }
+            if !m.is_finite() || *m <= 0.0 {
+                return Err(format!(
+                    "sensor.line_m.{}: must be a number greater than 0",
+                    position
+                ));
+            }
+        }
+        if self.sensor.t < 0.0 {
+            return Err("sensor.t: must not be negative".to_string());
+        }
+        let [low, high] = self.sensor.band;
+        if !low.is_finite() || !high.is_finite() || low < 0.0 || low >= high {
+            return Err("sensor.band: must be [low, high] hz with 0 <= low < high".to_string());
+        }
+        if self.pumps.refill_after < 1 {
+            return Err("pumps.refill_after: must be at least 1".to_string());
+        }
+        if self.watchdog.max_on_secs == 0 {
+            return Err("watchdog.max_on_secs: must be greater than 0".to_string());
+        }
+        for (pin, secs) in &self.watchdog.pins {
+            match pin.parse::<u8>() {
+                Ok(gpio) if gpio <= 27 => {}
+                _ => {
+                    return Err(format!(
+                        "watchdog.pins.{}: there is no gpio {} on rpi",
+                        pin, pin
+                    ))
+                }
+            }
+            if *secs == 0 {
+                return Err(format!("watchdog.pins.{}: must be greater than 0", pin));
+            }
+        }
+        if self.calibration.secs == 0 {
+            return Err("calibration.secs: must be greater than 0".to_string());
+        }
+        if !self.calibration.sigmas.is_finite() || self.calibration.sigmas <= 0.0 {
+            return Err("calibration.sigmas: must be greater than 0".to_string());
+        }
+        if self.fallback.min_samples == 0 {
+            return Err("fallback.min_samples: must be at least 1".to_string());
+        }
+        if self.fallback.min_range < 0 {
+            return Err("fallback.min_range: must not be negative".to_string());
+        }
+        if self.sockets.outbox_limit == 0 {
+            return Err("sockets.outbox_limit: must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn valid_colour(colour: &str) -> bool {
+    let parts = match colour
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(")"))
+    {
+        Some(inner) => inner.split(", ").collect::<Vec<&str>>(),
+        None => return false,
+    };
+
+    parts.len() == 3 && parts.iter().all(|part| part.parse::<u8>().is_ok())
+}
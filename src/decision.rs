@@ -0,0 +1,257 @@
+use std::f32::consts::PI;
+
+use crate::config::{self, Method};
+use crate::iching::Line;
+
+// one window of the sensor as the methods see it
+pub struct Signal<'a> {
+    // as the teensy sent them
+    pub raw: &'a [i32],
+    // bias taken away, gain applied
+    pub normalised: &'a [f32],
+    // samples per second in this window
+    pub rate: f32,
+}
+
+// turns a window of pip data into yin or yang
+pub trait Decision {
+    fn decide(&self, signal: &Signal) -> Line;
+}
+
+pub fn method(config: &config::Sensor) -> Box<dyn Decision> {
+    match config.method {
+        Method::Extrema => Box::new(Extrema { t: config.t }),
+        Method::Mean => Box::new(Mean),
+        Method::ZeroCrossing => Box::new(ZeroCrossing),
+        Method::Slope => Box::new(Slope),
+        Method::Spectral => Box::new(Spectral { band: config.band }),
+        Method::LsbParity => Box::new(LsbParity),
+    }
+}
+
+fn line(yang: bool) -> Line {
+    if yang {
+        Line::Yang
+    } else {
+        Line::Yin
+    }
+}
+
+// local maxima above t and local minima below -t
+pub fn extrema(normalised: &[f32], t: f32) -> (usize, usize) {
+    let mut maxima = 0;
+    let mut minima = 0;
+    for i in normalised.windows(3) {
+        if i[1] > i[0] && i[1] > i[2] && i[1] > t {
+            maxima += 1;
+        }
+        if i[1] < i[0] && i[1] < i[2] && i[1].abs() > t {
+            minima += 1;
+        }
+    }
+    (maxima, minima)
+}
+
+// the first one: more peaks than dips is yang
+pub struct Extrema {
+    t: f32,
+}
+
+impl Decision for Extrema {
+    fn decide(&self, signal: &Signal) -> Line {
+        let (maxima, minima) = extrema(signal.normalised, self.t);
+        line(maxima > minima)
+    }
+}
+
+// the plant was above its bias on average
+pub struct Mean;
+
+impl Decision for Mean {
+    fn decide(&self, signal: &Signal) -> Line {
+        if signal.normalised.is_empty() {
+            return Line::Yin;
+        }
+        let mean = signal.normalised.iter().sum::<f32>() / signal.normalised.len() as f32;
+        line(mean > 0.0)
+    }
+}
+
+// odd number of crossings of the bias is yang
+pub struct ZeroCrossing;
+
+impl Decision for ZeroCrossing {
+    fn decide(&self, signal: &Signal) -> Line {
+        let crossings = signal
+            .normalised
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        line(crossings % 2 == 1)
+    }
+}
+
+// least squares line through the window goes up
+pub struct Slope;
+
+impl Decision for Slope {
+    fn decide(&self, signal: &Signal) -> Line {
+        let n = signal.normalised.len() as f32;
+        if n < 2.0 {
+            return Line::Yin;
+        }
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = signal.normalised.iter().sum::<f32>() / n;
+        let covariance = signal
+            .normalised
+            .iter()
+            .enumerate()
+            .map(|(x, y)| (x as f32 - mean_x) * (y - mean_y))
+            .sum::<f32>();
+        line(covariance > 0.0)
+    }
+}
+
+// most of the energy (without the constant part) is in the band
+pub struct Spectral {
+    // hz, low and high
+    band: [f32; 2],
+}
+
+impl Decision for Spectral {
+    fn decide(&self, signal: &Signal) -> Line {
+        let n = signal.normalised.len();
+        if n < 2 || signal.rate <= 0.0 {
+            return Line::Yin;
+        }
+
+        // a plain dft, the windows are a few dozen samples
+        let mut inside = 0.0;
+        let mut outside = 0.0;
+        for k in 1..=n / 2 {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, value) in signal.normalised.iter().enumerate() {
+                let angle = 2.0 * PI * (k * i) as f32 / n as f32;
+                re += value * angle.cos();
+                im -= value * angle.sin();
+            }
+            let energy = re * re + im * im;
+            let frequency = k as f32 * signal.rate / n as f32;
+            if frequency >= self.band[0] && frequency <= self.band[1] {
+                inside += energy;
+            } else {
+                outside += energy;
+            }
+        }
+        line(inside > outside)
+    }
+}
+
+// the noise in the last bit of every sample, like coins
+pub struct LsbParity;
+
+impl Decision for LsbParity {
+    fn decide(&self, signal: &Signal) -> Line {
+        let ones = signal.raw.iter().filter(|value| *value & 1 == 1).count();
+        line(ones % 2 == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(method: &dyn Decision, normalised: &[f32], rate: f32) -> Line {
+        let raw = normalised.iter().map(|v| *v as i32).collect::<Vec<i32>>();
+        method.decide(&Signal {
+            raw: &raw,
+            normalised,
+            rate,
+        })
+    }
+
+    #[test]
+    fn extrema_counts_peaks_over_t() {
+        let peaks = Extrema { t: 10.0 };
+        assert_eq!(
+            decide(&peaks, &[0.0, 50.0, 0.0, 50.0, 0.0], 1.0),
+            Line::Yang
+        );
+        assert_eq!(
+            decide(&peaks, &[0.0, -50.0, 0.0, -50.0, 0.0], 1.0),
+            Line::Yin
+        );
+        // under t nothing counts
+        assert_eq!(extrema(&[0.0, 5.0, 0.0, -5.0, 0.0], 10.0), (0, 0));
+        assert_eq!(decide(&peaks, &[], 1.0), Line::Yin);
+    }
+
+    #[test]
+    fn mean_above_the_bias() {
+        assert_eq!(decide(&Mean, &[1.0, 2.0, -1.0], 1.0), Line::Yang);
+        assert_eq!(decide(&Mean, &[-1.0, -2.0, 1.0], 1.0), Line::Yin);
+        assert_eq!(decide(&Mean, &[0.0, 0.0], 1.0), Line::Yin);
+        assert_eq!(decide(&Mean, &[], 1.0), Line::Yin);
+    }
+
+    #[test]
+    fn zero_crossings_odd() {
+        assert_eq!(decide(&ZeroCrossing, &[-1.0, 1.0], 1.0), Line::Yang);
+        assert_eq!(decide(&ZeroCrossing, &[-1.0, 1.0, -1.0], 1.0), Line::Yin);
+        assert_eq!(decide(&ZeroCrossing, &[1.0], 1.0), Line::Yin);
+        assert_eq!(decide(&ZeroCrossing, &[], 1.0), Line::Yin);
+    }
+
+    #[test]
+    fn slope_goes_up() {
+        assert_eq!(decide(&Slope, &[-3.0, -1.0, 0.0, 2.0], 1.0), Line::Yang);
+        assert_eq!(decide(&Slope, &[3.0, 1.0, 0.0, -2.0], 1.0), Line::Yin);
+        assert_eq!(decide(&Slope, &[1.0, 1.0, 1.0], 1.0), Line::Yin);
+        assert_eq!(decide(&Slope, &[5.0], 1.0), Line::Yin);
+        assert_eq!(decide(&Slope, &[], 1.0), Line::Yin);
+    }
+
+    #[test]
+    fn spectral_energy_in_the_band() {
+        // 1 hz sampled at 8 hz, and 4 hz (nyquist) at 8 hz
+        let slow = (0..16)
+            .map(|i| (2.0 * PI * i as f32 / 8.0).sin())
+            .collect::<Vec<f32>>();
+        let fast = (0..16)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect::<Vec<f32>>();
+        let spectral = Spectral { band: [0.5, 2.0] };
+        assert_eq!(decide(&spectral, &slow, 8.0), Line::Yang);
+        assert_eq!(decide(&spectral, &fast, 8.0), Line::Yin);
+        assert_eq!(decide(&spectral, &slow, 0.0), Line::Yin);
+        assert_eq!(decide(&spectral, &slow, -8.0), Line::Yin);
+        assert_eq!(decide(&spectral, &[1.0], 8.0), Line::Yin);
+        assert_eq!(decide(&spectral, &[], 8.0), Line::Yin);
+    }
+
+    #[test]
+    fn lsb_parity_of_odd_samples() {
+        let parity = |raw: &[i32]| {
+            LsbParity.decide(&Signal {
+                raw,
+                normalised: &[],
+                rate: 1.0,
+            })
+        };
+        assert_eq!(parity(&[501, 502, 504]), Line::Yang);
+        assert_eq!(parity(&[501, 503, 504]), Line::Yin);
+        assert_eq!(parity(&[-1]), Line::Yang);
+        assert_eq!(parity(&[]), Line::Yin);
+    }
+
+    #[test]
+    fn method_follows_the_config() {
+        let mut config = config::Sensor {
+            method: Method::Mean,
+            ..Default::default()
+        };
+        assert_eq!(decide(&*method(&config), &[1.0], 1.0), Line::Yang);
+        config.method = Method::LsbParity;
+        assert_eq!(decide(&*method(&config), &[2.0], 1.0), Line::Yin);
+    }
+}
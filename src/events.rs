@@ -0,0 +1,68 @@
+use serde::Serialize;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::iching::Line;
+use crate::protocol::{Format, Outcome, Reply};
+use crate::wires::Reaction;
+
+// a slow client or subscriber must not hold the machine
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// what happens as it happens
+// {"version": 1, "reply": "event", "event": "line", "line": 1, "value": 0}
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Transition {
+        from: &'static str,
+        to: &'static str,
+    },
+    // a line of the hexagram, 1 is the bottom line
+    Line {
+        line: usize,
+        value: Line,
+    },
+    // a quick line for the related hexagram
+    Related {
+        line: usize,
+        value: Line,
+    },
+    Reaction(Reaction),
+    Result(Outcome),
+    // the reading panicked or had no hardware
+    Failure {
+        message: String,
+    },
+    // the watchdog forced an output low
+    Safety {
+        pin: u8,
+        on_secs: u64,
+    },
+}
+
+// connections which asked for the events, shared with the reading thread
+#[derive(Clone, Default)]
+pub struct Subscribers(Arc<Mutex<Vec<UnixStream>>>);
+
+impl Subscribers {
+    pub fn add(&self, stream: UnixStream) {
+        if let Err(error) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            println!("Can't subscribe: {:?}", error);
+            return;
+        }
+        if let Ok(mut streams) = self.0.lock() {
+            streams.push(stream);
+        }
+    }
+
+    // closed connections are dropped on the way
+    pub fn publish(&self, event: Event) {
+        let line = Reply::Event(event).encode(Format::Json);
+        if let Ok(mut streams) = self.0.lock() {
+            streams.retain(|mut stream| stream.write_all(line.as_bytes()).is_ok());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::{self, FallbackMethod};
+use crate::iching::{Line, LineValue};
+
+// the reading went the traditional way from this line on
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Fallback {
+    pub method: FallbackMethod,
+    // the first read without sensor data
+    pub failed: Read,
+    pub reason: String,
+    // cast lines wait here for their related line
+    #[serde(skip)]
+    values: [Option<LineValue>; 6],
+}
+
+// a window of the reading, {"line": 3} or {"related": 1}, 1 is the bottom
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Read {
+    Line(usize),
+    Related(usize),
+}
+
+impl fmt::Display for Read {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Read::Line(position) => write!(f, "line {}", position),
+            Read::Related(position) => write!(f, "related line {}", position),
+        }
+    }
+}
+
+impl Fallback {
+    pub fn new(method: FallbackMethod, failed: Read, reason: String) -> Self {
+        Fallback {
+            method,
+            failed,
+            reason,
+            values: [None; 6],
+        }
+    }
+
+    // a new line for the position
+    pub fn line(&mut self, position: usize) -> Line {
+        let value = cast(self.method, None);
+        self.values[position - 1] = Some(value);
+        value.line()
+    }
+
+    // the related line which makes the line moving or not
+    // a line read by the sensor only gets the moving part, with the odds of its polarity
+    pub fn related(&mut self, position: usize, line: Line) -> Line {
+        let value = match self.values[position - 1] {
+            Some(value) => value,
+            None => cast(self.method, Some(line)),
+        };
+        // get_related flips the lines which were read the same twice
+        if value.is_moving() {
+            line
+        } else {
+            line.flip()
+        }
+    }
+}
+
+// Err says what is wrong with the window
+pub fn usable(samples: &[i32], config: &config::Fallback) -> Result<(), String> {
+    if samples.len() < config.min_samples {
+        return Err(format!("{} samples", samples.len()));
+    }
+    let min = samples.iter().min().cloned().unwrap_or(0);
+    let max = samples.iter().max().cloned().unwrap_or(0);
+    // the teensy can send anything, i64 takes every i32 range
+    if (max as i64 - min as i64) < config.min_range as i64 {
+        return Err(format!("flat between {} and {}", min, max));
+    }
+
+    Ok(())
+}
+
+// one line value, with the given polarity if there is one
+fn cast(method: FallbackMethod, polarity: Option<Line>) -> LineValue {
+    let mut rng = rand::thread_rng();
+    loop {
+        let value = match method {
+            // heads 3, tails 2: 6 and 9 are 1/8, 7 and 8 are 3/8
+            // off never falls back, it's here for the match
+            FallbackMethod::Coins | FallbackMethod::Off => {
+                match (0..3).map(|_| rng.gen_range(2..=3)).sum::<u8>() {
+                    6 => LineValue::OldYin,
+                    7 => LineValue::YoungYang,
+                    8 => LineValue::YoungYin,
+                    _ => LineValue::OldYang,
+                }
+            }
+            // 6 is 1/16, 7 is 5/16, 8 is 7/16, 9 is 3/16
+            FallbackMethod::Yarrow => match rng.gen_range(0..16) {
+                0 => LineValue::OldYin,
+                1..=5 => LineValue::YoungYang,
+                6..=12 => LineValue::YoungYin,
+                _ => LineValue::OldYang,
+            },
+        };
+        if polarity.is_none_or(|line| line == value.line()) {
+            return value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usable_windows() {
+        let config = config::Fallback::default();
+        assert!(usable(&[500, 510, 490], &config).is_ok());
+        assert!(usable(&[500, 510], &config).is_err());
+        assert!(usable(&[], &config).is_err());
+        assert!(usable(&[500, 500, 500], &config).is_err());
+        assert!(usable(&[i32::MIN, 0, i32::MAX], &config).is_ok());
+        assert!(usable(&[i32::MIN, i32::MIN, i32::MIN], &config).is_err());
+    }
+}
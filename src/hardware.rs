@@ -0,0 +1,296 @@
+use rppal::gpio::Gpio;
+use rs_ws281x::Controller;
+use serialport::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{process, thread};
+
+use crate::acquisition::Acquisition;
+use crate::config::{self, Config};
+use crate::mock;
+
+// LEDs output, channel 0 is for the yao lines, channel 1 is for the li
+pub trait Leds {
+    fn leds_mut(&mut self, channel: usize) -> &mut [[u8; 4]];
+    fn render(&mut self) -> Result<(), String>;
+}
+
+// GPIO outputs
+pub trait Pins {
+    fn set_high(&mut self, pin: u8);
+    fn set_low(&mut self, pin: u8);
+
+    // a script runs the pin, it's high from the start of the script to its end
+    fn script_started(&mut self, _pin: u8) {}
+    fn script_ended(&mut self, _pin: u8) {}
+}
+
+// pip data source
+pub trait Sensor {
+    fn read(&mut self, delta: u64) -> Result<Vec<i32>, Disconnected>;
+
+    // frames which didn't make a sample, since the start
+    fn malformed(&self) -> u64 {
+        0
+    }
+}
+
+// the sensor is gone, there is no window to make a line from
+#[derive(Debug)]
+pub struct Disconnected(pub String);
+
+// external effects: sounds, fire and pumps scripts
+pub trait Effects {
+    // Err says why the effect didn't run
+    fn run(&mut self, effect: &str) -> Result<(), String>;
+}
+
+// everything the reading needs from the outer world
+pub struct Hardware {
+    pub leds: Option<Box<dyn Leds>>,
+    pub pins: Box<dyn Pins>,
+    pub sensor: Box<dyn Sensor>,
+    pub effects: Box<dyn Effects>,
+    pub config: Config,
+    pub delays: bool,
+    // set from outside to stop the reading
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl Hardware {
+    // the installation itself, the sensor comes with a running acquisition
+    pub fn new(config: Config, leds: Option<Box<dyn Leds>>, cancel: Arc<AtomicBool>) -> Self {
+        Hardware {
+            leds,
+            pins: Box::new(RpiPins),
+            sensor: Box::new(NoSensor),
+            effects: Box::new(ScriptEffects::new(&config.scripts.dir)),
+            config,
+            delays: true,
+            cancel,
+        }
+    }
+
+    // everything in memory, for an ordinary linux box
+    pub fn mock(mut config: Config, cancel: Arc<AtomicBool>) -> Self {
+        config.pumps.usage_file = mock_usage_file();
+        Hardware {
+            leds: Some(Box::new(mock::MockLeds::new(config.leds.leds_in_line))),
+            pins: Box::new(mock::MockPins::new()),
+            sensor: Box::new(mock::MockSensor::new()),
+            effects: Box::new(mock::MockEffects::new()),
+            config,
+            delays: true,
+            cancel,
+        }
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    // wait between the steps of the reading, cancel cuts it short
+    pub fn pause(&self, secs: u64) {
+        if !self.delays {
+            return;
+        }
+        let end = Instant::now() + Duration::from_secs(secs);
+        while !self.cancelled() && Instant::now() < end {
+            thread::sleep(Duration::from_millis(100).min(end - Instant::now()));
+        }
+    }
+}
+
+// mock pumps never touch the real usage file
+pub fn mock_usage_file() -> String {
+    std::env::temp_dir()
+        .join("pumps.mock.usage")
+        .to_string_lossy()
+        .to_string()
+}
+
+// ws281x strips
+impl Leds for Controller {
+    fn leds_mut(&mut self, channel: usize) -> &mut [[u8; 4]] {
+        Controller::leds_mut(self, channel)
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        Controller::render(self).map_err(|e| format!("{:?}", e))
+    }
+}
+
+// pins on rpi model 4
+pub struct RpiPins;
+
+impl Pins for RpiPins {
+    fn set_high(&mut self, pin: u8) {
+        if let Ok(gpio) = Gpio::new() {
+            if let Ok(pin) = gpio.get(pin) {
+                let mut pin = pin.into_output();
+                pin.set_high();
+            }
+        }
+    }
+
+    fn set_low(&mut self, pin: u8) {
+        if let Ok(gpio) = Gpio::new() {
+            if let Ok(pin) = gpio.get(pin) {
+                let mut pin = pin.into_output();
+                pin.set_low();
+            }
+        }
+    }
+}
+
+// a window gives up after the teensy went away that many times
+const RECONNECTS: u32 = 3;
+
+// teensy on the serial port
+// install arduino ide + teense support to read from serial port on rpi
+// the acquisition reads it all the time, a window is the samples between two instants
+pub struct SerialSensor {
+    acquisition: Acquisition,
+    reconnect: Duration,
+    // a port read comes back within its timeout
+    settle: Duration,
+    cancel: Arc<AtomicBool>,
+}
+
+impl SerialSensor {
+    pub fn new(acquisition: Acquisition, config: &config::Serial, cancel: Arc<AtomicBool>) -> Self {
+        SerialSensor {
+            acquisition,
+            reconnect: Duration::from_secs(config.reconnect_secs),
+            settle: Duration::from_secs(config.timeout_secs) + Duration::from_millis(100),
+            cancel,
+        }
+    }
+}
+
+impl Sensor for SerialSensor {
+    // a window cut by a lost port starts again on the new port
+    fn read(&mut self, delta: u64) -> Result<Vec<i32>, Disconnected> {
+        let mut lost = 0;
+        loop {
+            let port = self
+                .acquisition
+                .port(self.reconnect)
+                .map_err(Disconnected)?;
+            let start = Instant::now();
+            let end = start + Duration::from_secs(delta);
+            while !self.cancel.load(Ordering::SeqCst) && Instant::now() < end {
+                thread::sleep(Duration::from_millis(100).min(end - Instant::now()));
+            }
+            let end = Instant::now().min(end);
+            self.acquisition.settle(end, self.settle);
+
+            match self.acquisition.same_port(port) {
+                Ok(()) => return Ok(self.acquisition.window(start, end)),
+                Err(error) if lost < RECONNECTS => {
+                    eprintln!("Sensor lost: {}, reconnecting", error);
+                    lost += 1;
+                }
+                Err(error) => return Err(Disconnected(error)),
+            }
+        }
+    }
+
+    fn malformed(&self) -> u64 {
+        self.acquisition.malformed()
+    }
+}
+
+// for the commands which never read
+pub struct NoSensor;
+
+impl Sensor for NoSensor {
+    fn read(&mut self, _delta: u64) -> Result<Vec<i32>, Disconnected> {
+        Err(Disconnected("no sensor for this command".to_string()))
+    }
+}
+
+// the configured port or the usb device with the configured ids
+pub fn find_port(config: &config::Serial) -> Result<String, String> {
+    if !config.discovers() {
+        return Ok(config.port.clone());
+    }
+
+    let ports = serialport::available_ports().map_err(|e| e.to_string())?;
+    ports
+        .into_iter()
+        .find(|port| match &port.port_type {
+            serialport::SerialPortType::UsbPort(usb) => {
+                config.vid.is_none_or(|vid| vid == usb.vid)
+                    && config.pid.is_none_or(|pid| pid == usb.pid)
+                    && config
+                        .serial_number
+                        .as_ref()
+                        .is_none_or(|number| usb.serial_number.as_ref() == Some(number))
+            }
+            _ => false,
+        })
+        .map(|port| port.port_name)
+        .ok_or(format!(
+            "no usb serial device with vid {}, pid {}, serial number {}",
+            config
+                .vid
+                .map_or("any".to_string(), |vid| format!("{:#06x}", vid)),
+            config
+                .pid
+                .map_or("any".to_string(), |pid| format!("{:#06x}", pid)),
+            config.serial_number.as_deref().unwrap_or("any")
+        ))
+}
+
+pub fn open_port(path: &str, config: &config::Serial) -> Result<Box<dyn SerialPort>, String> {
+    let s = SerialPortSettings {
+        baud_rate: config.baud_rate,
+        data_bits: match config.data_bits {
+            5 => DataBits::Five,
+            6 => DataBits::Six,
+            7 => DataBits::Seven,
+            _ => DataBits::Eight,
+        },
+        flow_control: FlowControl::None,
+        parity: match config.parity {
+            config::Parity::None => Parity::None,
+            config::Parity::Odd => Parity::Odd,
+            config::Parity::Even => Parity::Even,
+        },
+        stop_bits: match config.stop_bits {
+            2 => StopBits::Two,
+            _ => StopBits::One,
+        },
+        timeout: Duration::from_secs(config.timeout_secs),
+    };
+
+    serialport::open_with_settings(path, &s).map_err(|e| format!("{}: {}", path, e))
+}
+
+// shell scripts on the installation
+pub struct ScriptEffects {
+    dir: String,
+}
+
+impl ScriptEffects {
+    pub fn new(dir: &str) -> Self {
+        ScriptEffects {
+            dir: dir.to_string(),
+        }
+    }
+}
+
+impl Effects for ScriptEffects {
+    fn run(&mut self, effect: &str) -> Result<(), String> {
+        println!("--------> {}", effect);
+
+        let cmd = format!("{}/{}.sh", self.dir, effect);
+        match process::Command::new(&cmd).output() {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(format!("{}: {}", cmd, output.status)),
+            Err(e) => Err(format!("{}: {}", cmd, e)),
+        }
+    }
+}
@@ -0,0 +1,576 @@
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+// lines go from the bottom to the top everywhere,
+// the bit-string form is "1" for yang and "0" for yin, bottom line first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Line {
+    Yin,
+    Yang,
+}
+
+impl Line {
+    pub fn bit(self) -> u8 {
+        match self {
+            Line::Yin => 0,
+            Line::Yang => 1,
+        }
+    }
+
+    pub fn flip(self) -> Self {
+        match self {
+            Line::Yin => Line::Yang,
+            Line::Yang => Line::Yin,
+        }
+    }
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '0' => Some(Line::Yin),
+            '1' => Some(Line::Yang),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.bit())
+    }
+}
+
+// on the socket a line is 0 or 1
+impl Serialize for Line {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.bit())
+    }
+}
+
+impl<'de> Deserialize<'de> for Line {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match u8::deserialize(deserializer)? {
+            0 => Ok(Line::Yin),
+            1 => Ok(Line::Yang),
+            bit => Err(de::Error::custom(format!("line {} is not 0 or 1", bit))),
+        }
+    }
+}
+
+// traditional line values, old lines are moving and change to the opposite
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineValue {
+    OldYin,
+    YoungYang,
+    YoungYin,
+    OldYang,
+}
+
+impl LineValue {
+    pub fn new(line: Line, moving: bool) -> Self {
+        match (line, moving) {
+            (Line::Yin, true) => LineValue::OldYin,
+            (Line::Yang, false) => LineValue::YoungYang,
+            (Line::Yin, false) => LineValue::YoungYin,
+            (Line::Yang, true) => LineValue::OldYang,
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        match self {
+            LineValue::OldYin => 6,
+            LineValue::YoungYang => 7,
+            LineValue::YoungYin => 8,
+            LineValue::OldYang => 9,
+        }
+    }
+
+    pub fn line(self) -> Line {
+        match self {
+            LineValue::OldYin | LineValue::YoungYin => Line::Yin,
+            LineValue::YoungYang | LineValue::OldYang => Line::Yang,
+        }
+    }
+
+    pub fn is_moving(self) -> bool {
+        self == LineValue::OldYin || self == LineValue::OldYang
+    }
+
+    // the line in the related hexagram
+    pub fn changed(self) -> Line {
+        if self.is_moving() {
+            self.line().flip()
+        } else {
+            self.line()
+        }
+    }
+}
+
+// on the socket a line value is 6, 7, 8 or 9
+impl Serialize for LineValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.number())
+    }
+}
+
+impl<'de> Deserialize<'de> for LineValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match u8::deserialize(deserializer)? {
+            6 => Ok(LineValue::OldYin),
+            7 => Ok(LineValue::YoungYang),
+            8 => Ok(LineValue::YoungYin),
+            9 => Ok(LineValue::OldYang),
+            number => Err(de::Error::custom(format!(
+                "line value {} is not 6, 7, 8 or 9",
+                number
+            ))),
+        }
+    }
+}
+
+// named the way the installation names them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigram {
+    Heaven,
+    Cloud,
+    Sun,
+    Wind,
+    Thunder,
+    Water,
+    Mountain,
+    Earth,
+}
+
+impl Trigram {
+    pub fn new(lines: [Line; 3]) -> Self {
+        use Line::*;
+        match lines {
+            [Yang, Yang, Yang] => Trigram::Heaven,
+            [Yang, Yang, Yin] => Trigram::Cloud,
+            [Yang, Yin, Yang] => Trigram::Sun,
+            [Yin, Yang, Yang] => Trigram::Wind,
+            [Yang, Yin, Yin] => Trigram::Thunder,
+            [Yin, Yang, Yin] => Trigram::Water,
+            [Yin, Yin, Yang] => Trigram::Mountain,
+            [Yin, Yin, Yin] => Trigram::Earth,
+        }
+    }
+
+    pub fn lines(self) -> [Line; 3] {
+        use Line::*;
+        match self {
+            Trigram::Heaven => [Yang, Yang, Yang],
+            Trigram::Cloud => [Yang, Yang, Yin],
+            Trigram::Sun => [Yang, Yin, Yang],
+            Trigram::Wind => [Yin, Yang, Yang],
+            Trigram::Thunder => [Yang, Yin, Yin],
+            Trigram::Water => [Yin, Yang, Yin],
+            Trigram::Mountain => [Yin, Yin, Yang],
+            Trigram::Earth => [Yin, Yin, Yin],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Trigram::Heaven => "Heaven",
+            Trigram::Cloud => "Cloud",
+            Trigram::Sun => "Sun",
+            Trigram::Wind => "Wind",
+            Trigram::Thunder => "Thunder",
+            Trigram::Water => "Water",
+            Trigram::Mountain => "Mountain",
+            Trigram::Earth => "Earth",
+        }
+    }
+
+    pub fn chinese_name(self) -> &'static str {
+        match self {
+            Trigram::Heaven => "Qian",
+            Trigram::Cloud => "Dui",
+            Trigram::Sun => "Li",
+            Trigram::Wind => "Xun",
+            Trigram::Thunder => "Zhen",
+            Trigram::Water => "Kan",
+            Trigram::Mountain => "Gen",
+            Trigram::Earth => "Kun",
+        }
+    }
+
+    // row and column in the king wen table
+    fn king_wen_index(self) -> usize {
+        match self {
+            Trigram::Heaven => 0,
+            Trigram::Thunder => 1,
+            Trigram::Water => 2,
+            Trigram::Mountain => 3,
+            Trigram::Earth => 4,
+            Trigram::Wind => 5,
+            Trigram::Sun => 6,
+            Trigram::Cloud => 7,
+        }
+    }
+}
+
+impl fmt::Display for Trigram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines().iter() {
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Trigram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = parse_lines(s, 3)?;
+        Ok(Trigram::new([lines[0], lines[1], lines[2]]))
+    }
+}
+
+// king wen numbers, lower trigram is the row, upper trigram is the column,
+// both in order heaven, thunder, water, mountain, earth, wind, sun, cloud
+const KING_WEN: [[u8; 8]; 8] = [
+    [1, 34, 5, 26, 11, 9, 14, 43],
+    [25, 51, 3, 27, 24, 42, 21, 17],
+    [6, 40, 29, 4, 7, 59, 64, 47],
+    [33, 62, 39, 52, 15, 53, 56, 31],
+    [12, 16, 8, 23, 2, 20, 35, 45],
+    [44, 32, 48, 18, 46, 57, 50, 28],
+    [13, 55, 63, 22, 36, 37, 30, 49],
+    [10, 54, 60, 41, 19, 61, 38, 58],
+];
+
+// in king wen order
+const NAMES: [&str; 64] = [
+    "The Creative",
+    "The Receptive",
+    "Difficulty at the Beginning",
+    "Youthful Folly",
+    "Waiting",
+    "Conflict",
+    "The Army",
+    "Holding Together",
+    "The Taming Power of the Small",
+    "Treading",
+    "Peace",
+    "Standstill",
+    "Fellowship with Men",
+    "Possession in Great Measure",
+    "Modesty",
+    "Enthusiasm",
+    "Following",
+    "Work on What Has Been Spoiled",
+    "Approach",
+    "Contemplation",
+    "Biting Through",
+    "Grace",
+    "Splitting Apart",
+    "Return",
+    "Innocence",
+    "The Taming Power of the Great",
+    "The Corners of the Mouth",
+    "Preponderance of the Great",
+    "The Abysmal",
+    "The Clinging",
+    "Influence",
+    "Duration",
+    "Retreat",
+    "The Power of the Great",
+    "Progress",
+    "Darkening of the Light",
+    "The Family",
+    "Opposition",
+    "Obstruction",
+    "Deliverance",
+    "Decrease",
+    "Increase",
+    "Break-through",
+    "Coming to Meet",
+    "Gathering Together",
+    "Pushing Upward",
+    "Oppression",
+    "The Well",
+    "Revolution",
+    "The Caldron",
+    "The Arousing",
+    "Keeping Still",
+    "Development",
+    "The Marrying Maiden",
+    "Abundance",
+    "The Wanderer",
+    "The Gentle",
+    "The Joyous",
+    "Dispersion",
+    "Limitation",
+    "Inner Truth",
+    "Preponderance of the Small",
+    "After Completion",
+    "Before Completion",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hexagram([Line; 6]);
+
+impl Hexagram {
+    pub fn new(lines: [Line; 6]) -> Self {
+        Hexagram(lines)
+    }
+
+    pub fn lines(&self) -> [Line; 6] {
+        self.0
+    }
+
+    pub fn lower(&self) -> Trigram {
+        Trigram::new([self.0[0], self.0[1], self.0[2]])
+    }
+
+    pub fn upper(&self) -> Trigram {
+        Trigram::new([self.0[3], self.0[4], self.0[5]])
+    }
+
+    pub fn king_wen(&self) -> u8 {
+        KING_WEN[self.lower().king_wen_index()][self.upper().king_wen_index()]
+    }
+
+    // shao yong's binary order, heaven is 1 and earth is 64
+    pub fn fu_xi(&self) -> u8 {
+        let mut number = 0;
+        for (i, line) in self.0.iter().enumerate() {
+            if *line == Line::Yin {
+                number += 1 << (5 - i);
+            }
+        }
+        number + 1
+    }
+
+    pub fn name(&self) -> &'static str {
+        NAMES[self.king_wen() as usize - 1]
+    }
+}
+
+impl fmt::Display for Hexagram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.0.iter() {
+            write!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Hexagram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = parse_lines(s, 6)?;
+        let mut hexagram = [Line::Yin; 6];
+        hexagram.copy_from_slice(&lines);
+        Ok(Hexagram(hexagram))
+    }
+}
+
+// on the socket a hexagram is the bit-string
+impl Serialize for Hexagram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hexagram {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+// the whole result: six line values, bottom to top
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cast([LineValue; 6]);
+
+impl Cast {
+    // lines that differ between the hexagram and the related one are moving
+    pub fn from_hexagrams(hexagram: &Hexagram, related: &Hexagram) -> Self {
+        let (h, r) = (hexagram.lines(), related.lines());
+        let mut values = [LineValue::YoungYin; 6];
+        for i in 0..6 {
+            values[i] = LineValue::new(h[i], h[i] != r[i]);
+        }
+        Cast(values)
+    }
+
+    pub fn values(&self) -> [LineValue; 6] {
+        self.0
+    }
+
+    pub fn hexagram(&self) -> Hexagram {
+        let mut lines = [Line::Yin; 6];
+        for (i, value) in self.0.iter().enumerate() {
+            lines[i] = value.line();
+        }
+        Hexagram(lines)
+    }
+
+    pub fn related(&self) -> Hexagram {
+        let mut lines = [Line::Yin; 6];
+        for (i, value) in self.0.iter().enumerate() {
+            lines[i] = value.changed();
+        }
+        Hexagram(lines)
+    }
+
+    // positions of the moving lines, 1 is the bottom line
+    pub fn moving(&self) -> Vec<usize> {
+        (1..=6).filter(|i| self.0[i - 1].is_moving()).collect()
+    }
+}
+
+fn parse_lines(s: &str, count: usize) -> Result<Vec<Line>, String> {
+    let lines = s
+        .chars()
+        .map(Line::from_char)
+        .collect::<Option<Vec<Line>>>()
+        .ok_or(format!("\"{}\" has not only 0 and 1", s))?;
+    if lines.len() != count {
+        return Err(format!("\"{}\" has not {} lines", s, count));
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hexagram(s: &str) -> Hexagram {
+        s.parse().unwrap()
+    }
+
+    // every bit-string, bottom line first
+    fn all() -> Vec<String> {
+        (0..64)
+            .map(|n: u32| {
+                (0..6)
+                    .map(|i| if n & (1 << i) != 0 { '1' } else { '0' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn known_hexagrams() {
+        let known = [
+            ("111111", 1, 1, "The Creative"),
+            ("000000", 2, 64, "The Receptive"),
+            ("100010", 3, 30, "Difficulty at the Beginning"),
+            ("010001", 4, 47, "Youthful Folly"),
+            ("111000", 11, 8, "Peace"),
+            ("000111", 12, 57, "Standstill"),
+            ("100000", 24, 32, "Return"),
+            ("011111", 44, 33, "Coming to Meet"),
+            ("101010", 63, 22, "After Completion"),
+            ("010101", 64, 43, "Before Completion"),
+        ];
+        for (bits, king_wen, fu_xi, name) in known.iter() {
+            let h = hexagram(bits);
+            assert_eq!(h.king_wen(), *king_wen, "{}", bits);
+            assert_eq!(h.fu_xi(), *fu_xi, "{}", bits);
+            assert_eq!(h.name(), *name, "{}", bits);
+        }
+    }
+
+    #[test]
+    fn numbers_are_one_to_sixty_four_once() {
+        let mut king_wen = all()
+            .iter()
+            .map(|s| hexagram(s).king_wen())
+            .collect::<Vec<u8>>();
+        let mut fu_xi = all()
+            .iter()
+            .map(|s| hexagram(s).fu_xi())
+            .collect::<Vec<u8>>();
+        king_wen.sort_unstable();
+        fu_xi.sort_unstable();
+        let expected = (1..=64).collect::<Vec<u8>>();
+        assert_eq!(king_wen, expected);
+        assert_eq!(fu_xi, expected);
+    }
+
+    #[test]
+    fn trigrams_make_the_hexagram() {
+        let h = hexagram("100010");
+        assert_eq!(h.lower(), Trigram::Thunder);
+        assert_eq!(h.upper(), Trigram::Water);
+        assert_eq!(h.lines()[0], Line::Yang);
+        assert_eq!("001".parse::<Trigram>(), Ok(Trigram::Mountain));
+        assert_eq!(Trigram::Mountain.to_string(), "001");
+    }
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        for s in all() {
+            assert_eq!(hexagram(&s).to_string(), s);
+            let json = serde_json::to_string(&hexagram(&s)).unwrap();
+            assert_eq!(json, format!("\"{}\"", s));
+            assert_eq!(
+                serde_json::from_str::<Hexagram>(&json).unwrap(),
+                hexagram(&s)
+            );
+        }
+    }
+
+    #[test]
+    fn bad_strings_are_rejected() {
+        for bad in ["0102", "0000000", "00000", "", "01101a", " 011011"].iter() {
+            assert!(bad.parse::<Hexagram>().is_err(), "{:?}", bad);
+        }
+        assert!("0101".parse::<Trigram>().is_err());
+        assert!("2".parse::<Trigram>().is_err());
+        assert!(serde_json::from_str::<Hexagram>("\"0102\"").is_err());
+    }
+
+    #[test]
+    fn cast_from_hexagrams() {
+        let cast = Cast::from_hexagrams(&hexagram("101100"), &hexagram("001101"));
+        use LineValue::*;
+        assert_eq!(
+            cast.values(),
+            [OldYang, YoungYin, YoungYang, YoungYang, YoungYin, OldYin]
+        );
+        assert_eq!(cast.moving(), vec![1, 6]);
+        assert_eq!(cast.hexagram(), hexagram("101100"));
+        assert_eq!(cast.related(), hexagram("001101"));
+    }
+
+    #[test]
+    fn cast_without_moving_lines() {
+        let cast = Cast::from_hexagrams(&hexagram("110010"), &hexagram("110010"));
+        assert!(cast.moving().is_empty());
+        assert_eq!(cast.related(), cast.hexagram());
+    }
+
+    #[test]
+    fn line_values() {
+        use LineValue::*;
+        for (value, number, line, changed) in [
+            (OldYin, 6, Line::Yin, Line::Yang),
+            (YoungYang, 7, Line::Yang, Line::Yang),
+            (YoungYin, 8, Line::Yin, Line::Yin),
+            (OldYang, 9, Line::Yang, Line::Yin),
+        ]
+        .iter()
+        {
+            assert_eq!(value.number(), *number);
+            assert_eq!(value.line(), *line);
+            assert_eq!(value.changed(), *changed);
+            assert_eq!(LineValue::new(*line, value.is_moving()), *value);
+            let json = serde_json::to_string(value).unwrap();
+            assert_eq!(json, number.to_string());
+            assert_eq!(serde_json::from_str::<LineValue>(&json).unwrap(), *value);
+        }
+        assert!(serde_json::from_str::<LineValue>("5").is_err());
+        assert!(serde_json::from_str::<LineValue>("10").is_err());
+    }
+}
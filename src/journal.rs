@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{self, Method};
+use crate::iching::{Cast, Hexagram, LineValue};
+use crate::wires::Trace;
+
+// one reading, one line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    // unix seconds
+    pub timestamp: u64,
+    pub hexagram: Hexagram,
+    pub related: Hexagram,
+    pub values: [LineValue; 6],
+    pub moving: Vec<usize>,
+    pub m: f32,
+    pub b: f32,
+    pub t: f32,
+    // older lines were all extrema
+    #[serde(default)]
+    pub method: Method,
+    // serial, mock or replay, older lines were all the installation
+    #[serde(default = "serial")]
+    pub source: String,
+    #[serde(flatten)]
+    pub trace: Trace,
+}
+
+// a reading which died, kept next to the readings
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub timestamp: u64,
+    pub failure: String,
+    #[serde(default = "serial")]
+    pub source: String,
+}
+
+fn serial() -> String {
+    "serial".to_string()
+}
+
+// the journal has both, one per line
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Reading(Box<Entry>),
+    Failure(Failure),
+}
+
+impl Entry {
+    pub fn new(cast: &Cast, sensor: &config::Sensor, source: &str, trace: Trace) -> Self {
+        Entry {
+            timestamp: now(),
+            hexagram: cast.hexagram(),
+            related: cast.related(),
+            values: cast.values(),
+            moving: cast.moving(),
+            m: sensor.m,
+            b: sensor.b,
+            t: sensor.t,
+            method: sensor.method,
+            source: source.to_string(),
+            trace,
+        }
+    }
+}
+
+impl Failure {
+    pub fn new(message: &str, source: &str) -> Self {
+        Failure {
+            timestamp: now(),
+            failure: message.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// a lost journal line is not a reason to stop the installation
+pub fn append<T: Serialize>(path: &Path, entry: &T) {
+    let line = match serde_json::to_string(entry) {
+        Ok(line) => line,
+        Err(error) => {
+            println!("Can't encode journal entry: {:?}", error);
+            return;
+        }
+    };
+
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()));
+    if let Err(error) = written {
+        println!("Can't write journal {}: {:?}", path.display(), error);
+    }
+}
+
+// which entries to export
+pub struct Filter {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub hexagram: Option<Hexagram>,
+}
+
+impl Filter {
+    // journal [--since <time>] [--until <time>] [--hexagram <bits>]
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .map(|i| args.get(i + 1).ok_or(format!("{} needs a value", name)))
+                .transpose()
+        };
+
+        Ok(Filter {
+            since: value("--since")?.map(|s| parse_time(s)).transpose()?,
+            until: value("--until")?.map(|s| parse_time(s)).transpose()?,
+            hexagram: value("--hexagram")?
+                .map(|s| s.parse::<Hexagram>())
+                .transpose()
+                .map_err(|e| format!("bad --hexagram: {}", e))?,
+        })
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        let timestamp = match record {
+            Record::Reading(entry) => entry.timestamp,
+            Record::Failure(failure) => failure.timestamp,
+        };
+        let hexagram = match (record, self.hexagram) {
+            (_, None) => true,
+            (Record::Reading(entry), Some(h)) => entry.hexagram == h || entry.related == h,
+            (Record::Failure(_), Some(_)) => false,
+        };
+
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp < until)
+            && hexagram
+    }
+}
+
+// print the matching entries as json lines or csv, csv has the readings only
+pub fn export(path: &Path, filter: &Filter, csv: bool) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if csv {
+        println!(
+            "timestamp,hexagram,related,king_wen,related_king_wen,moving,m,b,t,method,extrema,reactions,fallback,source"
+        );
+    }
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        let record = match serde_json::from_str::<Record>(&line) {
+            Ok(record) => record,
+            Err(error) => {
+                eprintln!("{}:{}: {}", path.display(), n + 1, error);
+                continue;
+            }
+        };
+        if !filter.matches(&record) {
+            continue;
+        }
+
+        match (&record, csv) {
+            (Record::Reading(entry), true) => println!("{}", csv_row(entry)),
+            (Record::Failure(_), true) => {}
+            (_, false) => println!("{}", line),
+        }
+    }
+
+    Ok(())
+}
+
+// lists inside a cell are space separated, so no quoting is needed
+fn csv_row(entry: &Entry) -> String {
+    let moving = entry
+        .moving
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    // maxima/minima of every line window
+    let extrema = entry
+        .trace
+        .lines
+        .iter()
+        .map(|w| format!("{}/{}", w.maxima, w.minima))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let reactions = entry
+        .trace
+        .reactions
+        .iter()
+        .map(|r| format!("{}:{}", r.trigram, r.actions.join("+").replace(' ', "")))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    // the method when the lines were cast from some line on
+    let fallback = entry
+        .trace
+        .fallback
+        .as_ref()
+        .map_or("", |fallback| fallback.method.name());
+
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        entry.timestamp,
+        entry.hexagram,
+        entry.related,
+        entry.hexagram.king_wen(),
+        entry.related.king_wen(),
+        moving,
+        entry.m,
+        entry.b,
+        entry.t,
+        entry.method.name(),
+        extrema,
+        reactions,
+        fallback,
+        entry.source
+    )
+}
+
+// unix seconds or a utc day like 2020-09-13
+fn parse_time(s: &str) -> Result<u64, String> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let parts = s
+        .split('-')
+        .map(|part| part.parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|_| format!("bad time: {}", s))?;
+    match parts[..] {
+        [y, m, d] if (1..=12).contains(&m) && (1..=31).contains(&d) && y >= 1970 => {
+            Ok(days_from_civil(y, m, d) as u64 * 86400)
+        }
+        _ => Err(format!("bad time: {} (unix seconds or YYYY-MM-DD)", s)),
+    }
+}
+
+// days since 1970-01-01 of a gregorian date
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
@@ -1,58 +1,62 @@
+use crate::iching::Cast;
+
 // This is our state machine.
 pub struct IOracle<S> {
-    pub hexagram: String,
-    pub related: String,
     state: S,
 }
 
 // The states
 pub struct Resting;
 pub struct Reading;
-pub struct Displaying;
+// only a finished reading can be displayed
+pub struct Displaying {
+    cast: Cast,
+}
 
 // Initial state
 impl IOracle<Resting> {
     pub fn new() -> Self {
         println!("start in resting");
-        IOracle {
-            hexagram: "000000".to_string(),
-            related: "000000".to_string(),
-            state: Resting,
-        }
+        IOracle { state: Resting }
+    }
+}
+
+impl IOracle<Displaying> {
+    pub fn cast(&self) -> Cast {
+        self.state.cast
     }
 }
 
 // Transitions between states
 impl From<IOracle<Resting>> for IOracle<Reading> {
-    fn from(val: IOracle<Resting>) -> IOracle<Reading> {
+    fn from(_val: IOracle<Resting>) -> IOracle<Reading> {
         println!("resting -> reading");
-        IOracle {
-            hexagram: val.hexagram,
-            related: val.related,
-            state: Reading,
-        }
+        IOracle { state: Reading }
     }
 }
 
-impl From<IOracle<Reading>> for IOracle<Displaying> {
-    fn from(val: IOracle<Reading>) -> IOracle<Displaying> {
+// reading can be finished only with the result
+impl IOracle<Reading> {
+    pub fn finish(self, cast: Cast) -> IOracle<Displaying> {
         println!("reading -> displaying");
         IOracle {
-            hexagram: val.hexagram,
-            related: val.related,
-            state: Displaying,
+            state: Displaying { cast },
         }
     }
 }
 
+// reading can be dropped without the result
+impl IOracle<Reading> {
+    pub fn cancel(self) -> IOracle<Resting> {
+        println!("reading -> resting");
+        IOracle { state: Resting }
+    }
+}
+
 impl From<IOracle<Displaying>> for IOracle<Resting> {
-    fn from(val: IOracle<Displaying>) -> IOracle<Resting> {
+    fn from(_val: IOracle<Displaying>) -> IOracle<Resting> {
         println!("displaying -> resting");
-        IOracle {
-            hexagram: val.hexagram,
-            related: val.related,
-            state: Resting,
-        }
+        IOracle { state: Resting }
     }
 }
 
@@ -63,12 +67,11 @@ pub enum IOracleWrapper {
 }
 
 impl IOracleWrapper {
-    pub fn step(mut self) -> Self {
-        self = match self {
-            IOracleWrapper::Resting(val) => IOracleWrapper::Reading(val.into()),
-            IOracleWrapper::Reading(val) => IOracleWrapper::Displaying(val.into()),
-            IOracleWrapper::Displaying(val) => IOracleWrapper::Resting(val.into()),
-        };
-        self
+    pub fn name(&self) -> &'static str {
+        match self {
+            IOracleWrapper::Resting(_) => "resting",
+            IOracleWrapper::Reading(_) => "reading",
+            IOracleWrapper::Displaying(_) => "displaying",
+        }
     }
 }
@@ -1,95 +1,886 @@
+mod acquisition;
+mod calibration;
+mod commands;
+mod config;
+mod decision;
+mod events;
+mod fallback;
+mod hardware;
+mod iching;
+mod journal;
 mod machine;
+mod mock;
+mod outbox;
+mod protocol;
+mod replay;
+mod teensy;
+mod watchdog;
 mod wires;
 
+use serde::Serialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
-use std::time::Duration;
-use std::{fs, process, thread};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, process, thread};
 
-const IORACLE_SEND: &str = "/tmp/ioracle.send";
-const IORACLE_RETURN: &str = "/tmp/ioracle.return";
+// how long a cancelled reading may take to let go of the hardware on shutdown
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how to build the hardware for every reading
+#[derive(Clone, Serialize)]
+struct Options {
+    config: config::Config,
+    // run without the installation hardware
+    mock: bool,
+    // play back a capture instead of the live sensor
+    replay: Option<PathBuf>,
+    speed: f64,
+    // write the sensor data of every reading into a capture in this directory
+    record: Option<PathBuf>,
+    // every pin raised by the hardware is watched
+    #[serde(skip)]
+    watchdog: Option<watchdog::Watchdog>,
+    // the live sensor, read all the time
+    #[serde(skip)]
+    acquisition: Option<acquisition::Acquisition>,
+}
+
+impl Options {
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .map(|i| args.get(i + 1).ok_or(format!("{} needs a value", name)))
+                .transpose()
+        };
+
+        let speed = match value("--speed")? {
+            Some(speed) => speed
+                .parse::<f64>()
+                .ok()
+                .filter(|speed| *speed >= 0.0)
+                .ok_or(format!("bad --speed: {}", speed))?,
+            None => 1.0,
+        };
+
+        let mut config = match value("--config")? {
+            Some(path) => config::Config::load(Path::new(path), true)?,
+            None => config::Config::load(Path::new(config::CONFIG_FILE), false)?,
+        };
+
+        // the last calibration wins over sensor.b and sensor.t
+        if let Some(profile) = calibration::active(&config.calibration)? {
+            eprintln!(
+                "Calibration of {}: b = {}, t = {}",
+                profile.timestamp, profile.b, profile.t
+            );
+            config.sensor.b = profile.b;
+            config.sensor.t = profile.t;
+        }
+
+        let options = Options {
+            config,
+            mock: args.iter().any(|arg| arg == "--mock"),
+            replay: value("--replay")?.map(PathBuf::from),
+            speed,
+            record: value("--record")?.map(PathBuf::from),
+            watchdog: None,
+            acquisition: None,
+        };
+
+        // check the capture before we start
+        if let Some(path) = &options.replay {
+            replay::ReplaySensor::open(path, speed)?;
+        }
+
+        Ok(options)
+    }
+
+    // the usage file the hardware of this run counts in
+    fn pump_status(&self) -> protocol::PumpStatus {
+        let file = if self.mock || self.replay.is_some() {
+            hardware::mock_usage_file()
+        } else {
+            self.config.pumps.usage_file.clone()
+        };
+        protocol::PumpStatus {
+            runs: wires::pump_usage(Path::new(&file)),
+            refill_after: self.config.pumps.refill_after,
+        }
+    }
+
+    // where the readings come from, for the journal
+    fn source(&self) -> &'static str {
+        if self.replay.is_some() {
+            "replay"
+        } else if self.mock {
+            "mock"
+        } else {
+            "serial"
+        }
+    }
+
+    // the acquisition knows the port and its rate, reading or not
+    fn sensor_status(&self) -> protocol::SensorStatus {
+        match (&self.replay, &self.acquisition) {
+            (Some(path), _) => protocol::SensorStatus {
+                source: "replay",
+                connected: path.exists(),
+                rate: None,
+            },
+            (None, Some(acquisition)) => protocol::SensorStatus {
+                source: "serial",
+                connected: acquisition.connected(),
+                rate: acquisition.rate(),
+            },
+            (None, None) => protocol::SensorStatus {
+                source: "mock",
+                connected: true,
+                rate: None,
+            },
+        }
+    }
+
+    // the watchdog drops the pins with pins of its own
+    fn watch(&mut self, subscribers: &events::Subscribers) {
+        let pins: Box<dyn hardware::Pins + Send> = if self.mock || self.replay.is_some() {
+            Box::new(mock::MockPins::new())
+        } else {
+            Box::new(hardware::RpiPins)
+        };
+        self.watchdog = Some(watchdog::Watchdog::start(
+            &self.config.watchdog,
+            pins,
+            subscribers.clone(),
+        ));
+    }
+
+    // only the commands which read start the serial port
+    fn acquire(&mut self) {
+        if !self.mock && self.replay.is_none() {
+            self.acquisition = Some(acquisition::Acquisition::start(&self.config.serial));
+        }
+    }
+
+    // pins, LEDs and effects, the sensor as it is
+    fn outputs(&self, cancel: Arc<AtomicBool>) -> hardware::Hardware {
+        let mut hw = if self.mock || self.replay.is_some() {
+            hardware::Hardware::mock(self.config.clone(), cancel)
+        } else {
+            // let leds = wires::build_controller(&self.config.leds, self.config.leds.brightness);
+            // hardware::Hardware::new(
+            //     self.config.clone(),
+            //     leds.map(|c| Box::new(c) as Box<dyn hardware::Leds>),
+            //     cancel,
+            // )
+            hardware::Hardware::new(self.config.clone(), None, cancel)
+        };
+
+        if let Some(watchdog) = &self.watchdog {
+            let pins = std::mem::replace(&mut hw.pins, Box::new(mock::MockPins::new()));
+            hw.pins = Box::new(watchdog::WatchedPins::new(pins, watchdog.clone()));
+        }
+
+        hw
+    }
+
+    fn hardware(&self, cancel: Arc<AtomicBool>) -> Result<hardware::Hardware, String> {
+        let mut hw = self.outputs(cancel.clone());
+
+        if let Some(acquisition) = &self.acquisition {
+            hw.sensor = Box::new(hardware::SerialSensor::new(
+                acquisition.clone(),
+                &self.config.serial,
+                cancel,
+            ));
+        }
+        if let Some(path) = &self.replay {
+            hw.sensor = Box::new(replay::ReplaySensor::open(path, self.speed)?);
+            // accelerated replay skips the pauses too
+            hw.delays = self.speed == 1.0;
+        }
+        if let Some(path) = &self.record {
+            let sensor = std::mem::replace(&mut hw.sensor, Box::new(mock::MockSensor::new()));
+            hw.sensor = Box::new(replay::RecordingSensor::create(sensor, path)?);
+        }
+
+        Ok(hw)
+    }
+}
+
+const USAGE: &str = "usage: core [daemon] [--mock] [--replay <file> [--speed <f>]] [--record <dir>] [--config <path>]
+       core read-once
+       core test-leds
+       core test-pin <n> [--for <secs>]
+       core play <effect>
+       core sensor-dump [--for <secs>]
+       core calibrate [--for <secs>] [--history]
+       core journal [--since <time>] [--until <time>] [--hexagram <bits>] [--csv]";
+
+// flags followed by a value, and flags on their own
+const VALUE_FLAGS: [&str; 8] = [
+    "--config",
+    "--replay",
+    "--speed",
+    "--record",
+    "--for",
+    "--since",
+    "--until",
+    "--hexagram",
+];
+const SWITCHES: [&str; 3] = ["--mock", "--history", "--csv"];
+
+// the arguments which are not flags or their values, None for an unknown flag
+fn words(args: &[String]) -> Option<Vec<&str>> {
+    let mut words = vec![];
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if arg.starts_with("--") {
+            if !SWITCHES.contains(&arg.as_str()) {
+                return None;
+            }
+        } else {
+            words.push(arg.as_str());
+        }
+    }
+    Some(words)
+}
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let words = words(&args).unwrap_or_else(|| {
+        println!("{}", USAGE);
+        process::exit(2);
+    });
+    let mut options = Options::from_args(&args).unwrap_or_else(|error| {
+        println!("{}", error);
+        process::exit(1);
+    });
+
+    // flags only is the daemon, like it always was
+    let command = words.first().copied().unwrap_or("daemon");
+    let positional = words.get(1);
+    let secs = args
+        .iter()
+        .position(|arg| arg == "--for")
+        .and_then(|i| args.get(i + 1));
+
+    // the maintenance commands are watched too
+    let subscribers = events::Subscribers::default();
+    if command != "journal" {
+        options.watch(&subscribers);
+    }
+    let history = args.iter().any(|arg| arg == "--history");
+    let reads = ["daemon", "read-once", "sensor-dump", "calibrate"];
+    if reads.contains(&command) && !history {
+        options.acquire();
+    }
+
+    let done = match (command, positional) {
+        ("daemon", _) => daemon(options, subscribers),
+        ("read-once", _) => commands::read_once(&options),
+        ("test-leds", _) => commands::test_leds(&options),
+        ("test-pin", Some(&pin)) => commands::test_pin(&options, pin, secs),
+        ("play", Some(&effect)) => commands::play(&options, effect),
+        ("sensor-dump", _) => commands::sensor_dump(&options, secs),
+        ("calibrate", _) if history => commands::calibrations(&options),
+        ("calibrate", _) => commands::calibrate(&options, secs),
+        ("journal", _) => journal::Filter::from_args(&args).and_then(|filter| {
+            journal::export(
+                Path::new(&options.config.journal.path),
+                &filter,
+                args.iter().any(|arg| arg == "--csv"),
+            )
+        }),
+        _ => {
+            println!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = done {
+        println!("{}", error);
+        process::exit(1);
+    }
+}
+
+// bind the send socket and serve the installation until a signal
+fn daemon(options: Options, subscribers: events::Subscribers) -> ! {
+    let sockets = &options.config.sockets;
+    let timing = &options.config.timing;
+
     // check socket
-    if Path::new(IORACLE_SEND).exists() {
-        if let Err(error) = fs::remove_file(IORACLE_SEND) {
+    if Path::new(&sockets.send).exists() {
+        if let Err(error) = fs::remove_file(&sockets.send) {
             println!("{}", error);
             process::exit(1);
         };
     }
 
     // try to connect
-    let listener = UnixListener::bind(IORACLE_SEND).unwrap_or_else(|error| {
+    let listener = UnixListener::bind(&sockets.send).unwrap_or_else(|error| {
         println!("{}", error);
         process::exit(1);
     });
 
+    // the result goes out the way the reading was requested
+    let mut format = protocol::Format::Legacy;
+    let mut client: Option<UnixStream> = None;
+
+    // results the return socket didn't take yet
+    let mut outbox = outbox::Outbox::open(Path::new(&sockets.outbox), sockets.outbox_limit);
+
+    // the reading runs in its own thread, so cancel can come in meanwhile
+    let mut worker: Option<Worker> = None;
+    // everybody who asked to cancel this reading
+    let mut cancellers: Vec<UnixStream> = vec![];
+
+    // for the status
+    let mut entered = Instant::now();
+    let mut last: Option<protocol::Outcome> = None;
+    let mut failures = protocol::FailureStatus::default();
+
+    // clients and the reading talk to the machine through here
+    let (sender, receiver) = mpsc::channel();
+    listen(listener, &options, &sender, &subscribers);
+    watch_signals(&sender);
+
+    // timers: the user gets ready, the result is shown
+    let mut start_at: Option<Instant> = None;
+    let mut shown_until: Option<Instant> = None;
+    // set by SIGTERM or SIGINT, the reading gets this long to stop
+    let mut stop_by: Option<Instant> = None;
+
     // create machine at resting state
     let mut ioracle = machine::IOracleWrapper::Resting(machine::IOracle::new());
 
-    // listen and react
+    // wait for a message or the next timer
     loop {
-        match ioracle {
-            machine::IOracleWrapper::Resting(_) => {
+        let wake = [start_at, shown_until, outbox.next_try(), stop_by]
+            .iter()
+            .flatten()
+            .min()
+            .cloned();
+        let message = match wake {
+            Some(wake) => receiver
+                .recv_timeout(wake.saturating_duration_since(Instant::now()))
+                .ok(),
+            None => receiver.recv().ok(),
+        };
+
+        // status doesn't change anything
+        let message = match message {
+            Some(Message::Request(stream, Request::Status)) => {
+                let lines = worker
+                    .as_ref()
+                    .and_then(|worker| worker.lines.lock().ok().map(|lines| lines.clone()))
+                    .unwrap_or_default();
+                let state = ioracle.name();
+                answer(
+                    &stream,
+                    &machine_status(state, entered, lines, &last, &failures, &options, &outbox),
+                );
+                None
+            }
+            Some(Message::Signal(signal)) => {
+                println!("Got signal {}, stopping", signal);
+                stop_by = Some(Instant::now() + STOP_TIMEOUT);
+                if let Some(worker) = &worker {
+                    worker.cancel.store(true, Ordering::SeqCst);
+                }
+                None
+            }
+            message => message,
+        };
+
+        // retry undelivered results
+        outbox.flush(Path::new(&sockets.return_));
+
+        let before = ioracle.name();
+        ioracle = match ioracle {
+            machine::IOracleWrapper::Resting(v) => {
                 // reset LEDs ???
-                // if let Some(mut controller) = wires::build_controller(50) {
-                //     wires::render_resting(&mut controller);
+                // if let Some(mut controller) = wires::build_controller(&options.config.leds, options.config.leds.resting_brightness) {
+                //     wires::render_resting(&mut controller, &options.config.colours.li);
                 // };
-                // listen for incomings
-                if let Ok(_) = listener.set_nonblocking(true) {
-                    // waiting for message
-                    for stream in listener.incoming() {
-                        if let Ok(stream) = stream {
-                            let stream_reader = BufReader::new(stream);
-                            for line in stream_reader.lines() {
-                                if let Ok(line) = line {
-                                    if line == "read" {
-                                        // reset LEDs ???
-                                        // if let Some(mut controller) = wires::build_controller(50) {
-                                        //     wires::render_resting(&mut controller);
-                                        // };
-
-                                        // wating for user
-                                        thread::sleep(Duration::from_secs(4));
-                                        ioracle = ioracle.step();
-                                        break;
-                                    }
-                                };
+                match message {
+                    Some(Message::Request(stream, Request::Read(requested, delivery))) => {
+                        if start_at.is_some() {
+                            busy(&stream, requested);
+                        } else {
+                            if requested == protocol::Format::Json {
+                                answer(&stream, &protocol::Reply::Accepted);
                             }
+                            format = requested;
+                            client = match delivery {
+                                protocol::Delivery::Connection => Some(stream),
+                                protocol::Delivery::Return => None,
+                            };
+                            // wating for user
+                            start_at =
+                                Some(Instant::now() + Duration::from_secs(timing.wait_for_user));
                         }
-                        break;
+                    }
+                    Some(Message::Request(stream, Request::Cancel)) => {
+                        if start_at.take().is_some() {
+                            answer(&stream, &protocol::Reply::Cancelled);
+                            if let Some(stream) = client.take() {
+                                answer(&stream, &protocol::Reply::Cancelled);
+                            }
+                        } else {
+                            not_reading(&stream);
+                        }
+                    }
+                    _ => {}
+                }
+
+                match start_at {
+                    Some(at) if Instant::now() >= at => {
+                        // reset LEDs ???
+                        // if let Some(mut controller) = wires::build_controller(&options.config.leds, options.config.leds.resting_brightness) {
+                        //     wires::render_resting(&mut controller, &options.config.colours.li);
+                        // };
+                        start_at = None;
+                        worker = Some(Worker::start(&options, &client, &subscribers, &sender));
+                        machine::IOracleWrapper::Reading(v.into())
+                    }
+                    _ => machine::IOracleWrapper::Resting(v),
+                }
+            }
+            machine::IOracleWrapper::Reading(v) => match message {
+                Some(Message::Request(stream, Request::Read(requested, _))) => {
+                    busy(&stream, requested);
+                    machine::IOracleWrapper::Reading(v)
+                }
+                Some(Message::Request(stream, Request::Cancel)) => {
+                    if let Some(worker) = &worker {
+                        worker.cancel.store(true, Ordering::SeqCst);
+                    }
+                    cancellers.push(stream);
+                    machine::IOracleWrapper::Reading(v)
+                }
+                Some(Message::Finished(Ended::Done(cast, trace))) => {
+                    worker = None;
+                    // the reading ended before the cancel got to it
+                    for stream in cancellers.drain(..) {
+                        not_reading(&stream);
+                    }
+                    let fallback = trace.fallback.as_ref().map(|fallback| fallback.method);
+                    journal::append(
+                        Path::new(&options.config.journal.path),
+                        &journal::Entry::new(
+                            &cast,
+                            &options.config.sensor,
+                            options.source(),
+                            trace,
+                        ),
+                    );
+
+                    let v = v.finish(cast);
+                    let outcome = protocol::Outcome::new(&v.cast(), fallback);
+                    let result = protocol::Reply::Result(outcome.clone());
+                    last = Some(outcome.clone());
+                    subscribers.publish(events::Event::Result(outcome));
+
+                    // requesting connection first, return socket if it's gone
+                    let delivered = match client.take() {
+                        Some(stream) => answer(&stream, &result),
+                        None => false,
+                    };
+                    if !delivered {
+                        outbox.send(Path::new(&sockets.return_), result.encode(format));
+                    }
+
+                    // show result for a while
+                    // need 100s
+                    shown_until = Some(Instant::now() + Duration::from_secs(timing.display));
+                    machine::IOracleWrapper::Displaying(v)
+                }
+                Some(Message::Finished(Ended::Cancelled)) => {
+                    worker = None;
+                    // the cancellers and the waiting client hear about it
+                    for stream in cancellers.drain(..).chain(client.take()) {
+                        answer(&stream, &protocol::Reply::Cancelled);
+                    }
+                    machine::IOracleWrapper::Resting(v.cancel())
+                }
+                Some(Message::Finished(Ended::Failed(code, message))) => {
+                    worker = None;
+                    println!("Reading failed: {}", message);
+                    make_safe(&options);
+
+                    failures.count += 1;
+                    failures.last = Some(message.clone());
+                    journal::append(
+                        Path::new(&options.config.journal.path),
+                        &journal::Failure::new(&message, options.source()),
+                    );
+                    subscribers.publish(events::Event::Failure {
+                        message: message.clone(),
+                    });
+
+                    for stream in cancellers.drain(..) {
+                        answer(&stream, &protocol::Reply::Cancelled);
+                    }
+                    if let Some(stream) = client.take() {
+                        answer(&stream, &protocol::Reply::error(code, &message));
+                    }
+                    machine::IOracleWrapper::Resting(v.cancel())
+                }
+                _ => machine::IOracleWrapper::Reading(v),
+            },
+            machine::IOracleWrapper::Displaying(v) => {
+                match message {
+                    Some(Message::Request(stream, Request::Read(requested, _))) => {
+                        busy(&stream, requested);
+                    }
+                    Some(Message::Request(stream, Request::Cancel)) => not_reading(&stream),
+                    _ => {}
+                }
+
+                match shown_until {
+                    Some(until) if Instant::now() < until => machine::IOracleWrapper::Displaying(v),
+                    _ => {
+                        // if let Some(mut controller) = wires::build_controller(&options.config.leds, options.config.leds.resting_brightness) {
+                        //     wires::render_resting(&mut controller, &options.config.colours.li);
+                        // };
+                        shown_until = None;
+                        machine::IOracleWrapper::Resting(v.into())
                     }
                 }
             }
-            machine::IOracleWrapper::Reading(ref mut v) => {
-                // if let Some(mut controller) = wires::build_controller(255) {
-                //     let (hexagram, related) = wires::reading(&mut controller);
-                //     v.hexagram = hexagram;
-                //     v.related = related;
-                // }
-                let (hexagram, related) = wires::reading_no_led();
-                ioracle = ioracle.step();
+        };
+        if ioracle.name() != before {
+            entered = Instant::now();
+            subscribers.publish(events::Event::Transition {
+                from: before,
+                to: ioracle.name(),
+            });
+        }
+
+        // wait for the reading to stop, but not forever
+        if let Some(by) = stop_by {
+            if worker.is_none() {
+                shutdown(&options, 0);
+            } else if Instant::now() >= by {
+                println!("Reading didn't stop in {:?}", STOP_TIMEOUT);
+                shutdown(&options, 1);
+            }
+        }
+    }
+}
+
+// SIGTERM and SIGINT go to the main loop like everything else
+fn watch_signals(sender: &mpsc::Sender<Message>) {
+    let mut signals = match Signals::new([SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(error) => {
+            println!("Can't watch signals: {:?}", error);
+            process::exit(1);
+        }
+    };
+    let sender = sender.clone();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if sender.send(Message::Signal(signal)).is_err() {
+                break;
             }
-            machine::IOracleWrapper::Displaying(ref v) => {
-                match UnixStream::connect(IORACLE_RETURN) {
-                    Ok(mut stream) => {
-                        let result = format!("{}|{}", &v.hexagram, &v.related).into_bytes();
-                        if let Err(error) = stream.write_all(&result) {
-                            println!("Can't write to RETURN stream: {:?}", error);
-                        };
+        }
+    });
+}
+
+// pins down and LEDs dark with fresh hardware, whatever the reading left
+fn make_safe(options: &Options) {
+    let mut hw = options.outputs(Arc::new(AtomicBool::new(false)));
+    wires::drop_pins(&mut hw);
+    // the installation's outputs come without LEDs, open them to blank them
+    if hw.leds.is_none() {
+        match wires::build_controller(&options.config.leds, options.config.leds.brightness) {
+            Some(leds) => hw.leds = Some(Box::new(leds)),
+            None => println!("Can't open the LEDs to blank them"),
+        }
+    }
+    wires::blank(&mut hw);
+}
+
+// pins down, LEDs dark, no stale socket
+// exit code 0 is a clean stop, 1 means the reading had to be left behind
+fn shutdown(options: &Options, code: i32) -> ! {
+    make_safe(options);
+
+    if let Err(error) = fs::remove_file(&options.config.sockets.send) {
+        println!("Can't remove {}: {}", options.config.sockets.send, error);
+    }
+
+    println!("Stopped");
+    process::exit(code);
+}
+
+// what the main loop waits for
+enum Message {
+    // a client wants something from the machine, replies go to the stream
+    Request(UnixStream, Request),
+    // the reading thread is done
+    Finished(Ended),
+    // SIGTERM or SIGINT
+    Signal(i32),
+}
+
+// how the reading ended
+enum Ended {
+    Done(iching::Cast, wires::Trace),
+    Cancelled,
+    // panic, no hardware or no sensor, the machine goes on
+    Failed(protocol::ErrorCode, String),
+}
+
+// what a client wants from the machine itself
+enum Request {
+    Read(protocol::Format, protocol::Delivery),
+    Cancel,
+    Status,
+}
+
+// the reading thread and its cancel flag
+struct Worker {
+    cancel: Arc<AtomicBool>,
+    // lines read so far
+    lines: Arc<Mutex<Vec<iching::Line>>>,
+}
+
+impl Worker {
+    // the result comes back as Message::Finished
+    fn start(
+        options: &Options,
+        client: &Option<UnixStream>,
+        subscribers: &events::Subscribers,
+        sender: &mpsc::Sender<Message>,
+    ) -> Self {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = options.clone();
+        let flag = cancel.clone();
+        let progress = client.as_ref().and_then(|stream| stream.try_clone().ok());
+        let lines = Arc::new(Mutex::new(vec![]));
+        let read = lines.clone();
+        let subscribers = subscribers.clone();
+        let sender = sender.clone();
+
+        thread::spawn(move || {
+            let reading = thread::spawn(move || {
+                let mut hw = match options.hardware(flag) {
+                    Ok(hw) => hw,
+                    Err(error) => {
+                        return Ended::Failed(
+                            protocol::ErrorCode::ReadingFailed,
+                            format!("can't build hardware: {}", error),
+                        )
                     }
-                    Err(error) => println!("Can't connect to RETURN socket: {:?}", error),
                 };
+                let result = wires::reading(&mut hw, &mut |event| {
+                    if let events::Event::Line { line, value } = event {
+                        if let Ok(mut read) = read.lock() {
+                            read.push(value);
+                        }
+                        if let Some(stream) = &progress {
+                            answer(stream, &protocol::Reply::Progress { line, value });
+                        }
+                    }
+                    subscribers.publish(event);
+                });
+                match result {
+                    Ok((cast, trace)) => Ended::Done(cast, trace),
+                    Err(wires::Stopped::Cancelled) => Ended::Cancelled,
+                    Err(wires::Stopped::Disconnected(error)) => Ended::Failed(
+                        protocol::ErrorCode::SensorDisconnected,
+                        format!("sensor disconnected: {}", error),
+                    ),
+                    Err(wires::Stopped::NoData(reason)) => {
+                        Ended::Failed(protocol::ErrorCode::NoData, reason)
+                    }
+                }
+            });
 
-                // show result for a while
-                // need 100s
-                thread::sleep(Duration::from_secs(5));
-                // if let Some(mut controller) = wires::build_controller(50) {
-                //     wires::render_resting(&mut controller);
-                // };
-                ioracle = ioracle.step();
+            // the machine hears about the end even if the reading panicked
+            let ended = reading.join().unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown".to_string());
+                Ended::Failed(
+                    protocol::ErrorCode::ReadingFailed,
+                    format!("panic: {}", message),
+                )
+            });
+            let _ = sender.send(Message::Finished(ended));
+        });
+
+        Worker { cancel, lines }
+    }
+}
+
+// accept in the background, every client gets its own thread
+fn listen(
+    listener: UnixListener,
+    options: &Options,
+    sender: &mpsc::Sender<Message>,
+    subscribers: &events::Subscribers,
+) {
+    let options = Arc::new(options.clone());
+    let sender = sender.clone();
+    let subscribers = subscribers.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let options = options.clone();
+                    let sender = sender.clone();
+                    let subscribers = subscribers.clone();
+                    thread::spawn(move || serve(stream, &options, &sender, &subscribers));
+                }
+                Err(error) => println!("Can't accept: {:?}", error),
+            }
+        }
+    });
+}
+
+// answer what we can here, the machine decides the rest
+fn serve(
+    stream: UnixStream,
+    options: &Options,
+    sender: &mpsc::Sender<Message>,
+    subscribers: &events::Subscribers,
+) {
+    // a client which doesn't read its replies can't hold up the machine
+    if let Err(error) = stream.set_write_timeout(Some(events::WRITE_TIMEOUT)) {
+        println!("Can't serve: {:?}", error);
+        return;
+    }
+    let stream_reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(_) => return,
+    };
+    for line in stream_reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let request = match protocol::parse(&line) {
+            None => continue,
+            Some(Err(reply)) => {
+                answer(&stream, &reply);
+                continue;
+            }
+            Some(Ok((protocol::Command::Read(delivery), format))) => {
+                Request::Read(format, delivery)
+            }
+            Some(Ok((protocol::Command::Cancel, _))) => Request::Cancel,
+            Some(Ok((protocol::Command::Status, _))) => Request::Status,
+            Some(Ok((protocol::Command::Ping, _))) => {
+                answer(&stream, &protocol::Reply::Pong);
+                continue;
+            }
+            Some(Ok((protocol::Command::Samples(secs), _))) => {
+                let reply = match &options.acquisition {
+                    Some(acquisition) => protocol::Reply::Samples {
+                        rate: acquisition.rate(),
+                        samples: acquisition.latest(secs),
+                    },
+                    None => protocol::Reply::error(
+                        protocol::ErrorCode::NoLiveSensor,
+                        "the sensor is a mock or a replay",
+                    ),
+                };
+                answer(&stream, &reply);
+                continue;
+            }
+            Some(Ok((protocol::Command::Config, _))) => {
+                let config = serde_json::to_value(options).unwrap_or_default();
+                answer(&stream, &protocol::Reply::Config { config });
+                continue;
+            }
+            Some(Ok((protocol::Command::Subscribe, _))) => {
+                // subscribers stay for the events
+                if answer(&stream, &protocol::Reply::Subscribed) {
+                    if let Ok(stream) = stream.try_clone() {
+                        subscribers.add(stream);
+                    }
+                }
+                continue;
             }
         };
+
+        let reply_to = match stream.try_clone() {
+            Ok(reply_to) => reply_to,
+            Err(_) => break,
+        };
+        if sender.send(Message::Request(reply_to, request)).is_err() {
+            break;
+        }
+    }
+}
+
+// one reading at a time
+fn busy(stream: &UnixStream, format: protocol::Format) {
+    if format == protocol::Format::Json {
+        answer(
+            stream,
+            &protocol::Reply::error(protocol::ErrorCode::Busy, "reading in progress"),
+        );
+    } else {
+        println!("Reading in progress, read ignored");
+    }
+}
+
+fn not_reading(stream: &UnixStream) {
+    answer(
+        stream,
+        &protocol::Reply::error(protocol::ErrorCode::NotReading, "nothing to cancel"),
+    );
+}
+
+// what the kiosk shows about the machine
+fn machine_status(
+    state: &'static str,
+    entered: Instant,
+    lines: Vec<iching::Line>,
+    last: &Option<protocol::Outcome>,
+    failures: &protocol::FailureStatus,
+    options: &Options,
+    outbox: &outbox::Outbox,
+) -> protocol::Reply {
+    protocol::Reply::Status {
+        state,
+        state_secs: entered.elapsed().as_secs(),
+        lines,
+        last_result: last.clone(),
+        pumps: options.pump_status(),
+        sensor: options.sensor_status(),
+        failures: failures.clone(),
+        outbox: outbox.status(),
+    }
+}
+
+// json replies go back on the same connection
+// a client which didn't take a reply is cut off, it doesn't get to wait again
+fn answer(mut stream: &UnixStream, reply: &protocol::Reply) -> bool {
+    match stream.write_all(reply.encode(protocol::Format::Json).as_bytes()) {
+        Ok(_) => true,
+        Err(error) => {
+            println!("Can't write reply: {:?}", error);
+            let _ = stream.shutdown(Shutdown::Both);
+            false
+        }
     }
 }
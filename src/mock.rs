@@ -0,0 +1,140 @@
+use rand::distributions::{Distribution, Uniform};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::hardware::{Disconnected, Effects, Leds, Pins, Sensor};
+
+// both channels of every rendered frame
+type Renders = Arc<Mutex<Vec<[Vec<[u8; 4]>; 2]>>>;
+
+// in-memory strips, counts every render
+pub struct MockLeds {
+    channels: [Vec<[u8; 4]>; 2],
+    renders: Renders,
+}
+
+impl MockLeds {
+    pub fn new(leds_in_line: i32) -> Self {
+        let leds_in_line = leds_in_line as usize;
+        MockLeds {
+            channels: [
+                vec![[0; 4]; 6 * leds_in_line],
+                vec![[0; 4]; 3 * leds_in_line],
+            ],
+            renders: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    // every rendered frame
+    #[cfg(test)]
+    pub fn renders(&self) -> Renders {
+        self.renders.clone()
+    }
+}
+
+impl Leds for MockLeds {
+    fn leds_mut(&mut self, channel: usize) -> &mut [[u8; 4]] {
+        &mut self.channels[channel]
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        if let Ok(mut renders) = self.renders.lock() {
+            renders.push(self.channels.clone());
+        }
+        Ok(())
+    }
+}
+
+// records every pin change as (pin, high)
+pub struct MockPins {
+    log: Arc<Mutex<Vec<(u8, bool)>>>,
+}
+
+impl MockPins {
+    pub fn new() -> Self {
+        MockPins {
+            log: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn log(&self) -> Arc<Mutex<Vec<(u8, bool)>>> {
+        self.log.clone()
+    }
+}
+
+impl Pins for MockPins {
+    fn set_high(&mut self, pin: u8) {
+        println!("--------> mock pin {}: high", pin);
+        if let Ok(mut log) = self.log.lock() {
+            log.push((pin, true));
+        }
+    }
+
+    fn set_low(&mut self, pin: u8) {
+        println!("--------> mock pin {}: low", pin);
+        if let Ok(mut log) = self.log.lock() {
+            log.push((pin, false));
+        }
+    }
+}
+
+// gives scripted windows first, then random noise around the bias
+pub struct MockSensor {
+    windows: VecDeque<Vec<i32>>,
+}
+
+impl MockSensor {
+    pub fn new() -> Self {
+        MockSensor {
+            windows: VecDeque::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn scripted(windows: Vec<Vec<i32>>) -> Self {
+        MockSensor {
+            windows: windows.into(),
+        }
+    }
+}
+
+impl Sensor for MockSensor {
+    fn read(&mut self, delta: u64) -> Result<Vec<i32>, Disconnected> {
+        if let Some(window) = self.windows.pop_front() {
+            return Ok(window);
+        }
+
+        let mut rng = rand::thread_rng();
+        let noise = Uniform::from(470..530);
+        Ok((0..delta * 10).map(|_| noise.sample(&mut rng)).collect())
+    }
+}
+
+// records effect names
+pub struct MockEffects {
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockEffects {
+    pub fn new() -> Self {
+        MockEffects {
+            log: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn log(&self) -> Arc<Mutex<Vec<String>>> {
+        self.log.clone()
+    }
+}
+
+impl Effects for MockEffects {
+    fn run(&mut self, effect: &str) -> Result<(), String> {
+        println!("--------> mock {}", effect);
+        if let Ok(mut log) = self.log.lock() {
+            log.push(effect.to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const LAST_RETRY: Duration = Duration::from_secs(60);
+
+// results waiting for the return socket
+// kept on disk, one json string per line, oldest first
+pub struct Outbox {
+    path: PathBuf,
+    limit: usize,
+    queue: VecDeque<String>,
+    retry: Duration,
+    next_try: Instant,
+    attempts: u32,
+    delivered: u64,
+    dropped: u64,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutboxStatus {
+    pending: usize,
+    attempts: u32,
+    delivered: u64,
+    dropped: u64,
+    next_retry_secs: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl Outbox {
+    pub fn open(path: &Path, limit: usize) -> Self {
+        let mut queue = VecDeque::new();
+        if let Ok(file) = File::open(path) {
+            for line in BufReader::new(file).lines() {
+                match line.map(|line| serde_json::from_str::<String>(&line)) {
+                    Ok(Ok(message)) => queue.push_back(message),
+                    _ => println!("Outbox: skip broken line in {}", path.display()),
+                }
+            }
+        }
+        if !queue.is_empty() {
+            println!("Outbox: {} undelivered results", queue.len());
+        }
+
+        let mut outbox = Outbox {
+            path: path.to_path_buf(),
+            limit,
+            queue,
+            retry: FIRST_RETRY,
+            next_try: Instant::now(),
+            attempts: 0,
+            delivered: 0,
+            dropped: 0,
+            last_error: None,
+        };
+        outbox.trim();
+        outbox
+    }
+
+    // queue the message and try to deliver everything right away
+    pub fn send(&mut self, socket: &Path, message: String) {
+        self.queue.push_back(message);
+        self.trim();
+        self.save();
+        self.next_try = Instant::now();
+        self.flush(socket);
+    }
+
+    // deliver in order if it's time to retry
+    pub fn flush(&mut self, socket: &Path) {
+        if self.queue.is_empty() || Instant::now() < self.next_try {
+            return;
+        }
+
+        let mut sent = 0;
+        while let Some(message) = self.queue.front() {
+            match deliver(socket, message) {
+                Ok(_) => {
+                    self.queue.pop_front();
+                    self.delivered += 1;
+                    sent += 1;
+                    self.attempts = 0;
+                    self.retry = FIRST_RETRY;
+                    self.last_error = None;
+                }
+                Err(error) => {
+                    self.attempts += 1;
+                    self.next_try = Instant::now() + self.retry;
+                    println!(
+                        "Can't deliver to RETURN socket: {:?}, retry in {:?}",
+                        error, self.retry
+                    );
+                    self.retry = (self.retry * 2).min(LAST_RETRY);
+                    self.last_error = Some(error.to_string());
+                    break;
+                }
+            }
+        }
+
+        if sent > 0 {
+            self.save();
+        }
+    }
+
+    // when flush has something to do, None if nothing is waiting
+    pub fn next_try(&self) -> Option<Instant> {
+        if self.queue.is_empty() {
+            None
+        } else {
+            Some(self.next_try)
+        }
+    }
+
+    pub fn status(&self) -> OutboxStatus {
+        OutboxStatus {
+            pending: self.queue.len(),
+            attempts: self.attempts,
+            delivered: self.delivered,
+            dropped: self.dropped,
+            next_retry_secs: if self.queue.is_empty() {
+                None
+            } else {
+                Some(
+                    self.next_try
+                        .saturating_duration_since(Instant::now())
+                        .as_secs(),
+                )
+            },
+            last_error: self.last_error.clone(),
+        }
+    }
+
+    // oldest results go first when the outbox is full
+    fn trim(&mut self) {
+        while self.queue.len() > self.limit {
+            self.queue.pop_front();
+            self.dropped += 1;
+            println!("Outbox is full, oldest result dropped");
+        }
+    }
+
+    fn save(&self) {
+        let mut contents = String::new();
+        for message in &self.queue {
+            if let Ok(line) = serde_json::to_string(message) {
+                contents.push_str(&line);
+                contents.push('\n');
+            }
+        }
+
+        // write and rename, so a crash never leaves half a file
+        let tmp = self.path.with_extension("tmp");
+        if let Err(error) = fs::write(&tmp, contents).and_then(|_| fs::rename(&tmp, &self.path)) {
+            println!("Can't save outbox: {:?}", error);
+        }
+    }
+}
+
+fn deliver(socket: &Path, message: &str) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(message.as_bytes())
+}
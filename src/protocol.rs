@@ -0,0 +1,356 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::FallbackMethod;
+use crate::events::Event;
+use crate::iching::{Cast, Hexagram, Line, LineValue};
+use crate::outbox::OutboxStatus;
+
+// newline-delimited json on the ioracle.send socket
+// request: {"version": 1, "command": "read"}
+// reply:   {"version": 1, "reply": "accepted"}
+// the legacy "read" line and "hexagram|related" output keep working
+pub const VERSION: u64 = 1;
+
+// samples without secs
+const DEFAULT_SAMPLES_SECS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Read(Delivery),
+    Status,
+    Cancel,
+    Ping,
+    Config,
+    // keep the connection for the events
+    Subscribe,
+    // the latest seconds of the live sensor
+    // {"version": 1, "command": "samples", "secs": 10}
+    Samples(u64),
+}
+
+// where the result of the reading goes
+// {"version": 1, "command": "read", "delivery": "connection"}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    // push to the ioracle.return socket
+    Return,
+    // progress and result on the requesting connection
+    Connection,
+}
+
+// how the client talks to us
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Legacy,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadJson,
+    BadVersion,
+    BadRequest,
+    UnknownCommand,
+    NotReading,
+    Busy,
+    ReadingFailed,
+    SensorDisconnected,
+    // mock or replay, nothing is read all the time
+    NoLiveSensor,
+    // a window had no usable data and the fallback is off
+    NoData,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    Accepted,
+    Progress {
+        line: usize,
+        value: Line,
+    },
+    Result(Outcome),
+    Status {
+        state: &'static str,
+        state_secs: u64,
+        // lines of the reading in progress, bottom to top
+        lines: Vec<Line>,
+        last_result: Option<Outcome>,
+        pumps: PumpStatus,
+        sensor: SensorStatus,
+        failures: FailureStatus,
+        outbox: OutboxStatus,
+    },
+    // the reading stopped without the result
+    Cancelled,
+    Subscribed,
+    Event(Event),
+    // [unix milliseconds, value] pairs, oldest first
+    Samples {
+        rate: Option<f32>,
+        samples: Vec<(u64, i32)>,
+    },
+    Pong,
+    Config {
+        config: Value,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+// everything the frontend needs about a finished reading
+#[derive(Clone, Debug, Serialize)]
+pub struct Outcome {
+    hexagram: Hexagram,
+    related: Hexagram,
+    values: [LineValue; 6],
+    moving: Vec<usize>,
+    king_wen: [u8; 2],
+    fu_xi: [u8; 2],
+    names: [&'static str; 2],
+    // the sensor had no usable data, the lines were cast
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback: Option<FallbackMethod>,
+}
+
+impl Outcome {
+    pub fn new(cast: &Cast, fallback: Option<FallbackMethod>) -> Self {
+        let (hexagram, related) = (cast.hexagram(), cast.related());
+        Outcome {
+            hexagram,
+            related,
+            values: cast.values(),
+            moving: cast.moving(),
+            king_wen: [hexagram.king_wen(), related.king_wen()],
+            fu_xi: [hexagram.fu_xi(), related.fu_xi()],
+            names: [hexagram.name(), related.name()],
+            fallback,
+        }
+    }
+}
+
+// pump runs since the last refill
+#[derive(Debug, Serialize)]
+pub struct PumpStatus {
+    pub runs: Option<i32>,
+    pub refill_after: i32,
+}
+
+// where the pip data comes from and if it's there
+#[derive(Debug, Serialize)]
+pub struct SensorStatus {
+    pub source: &'static str,
+    pub connected: bool,
+    // samples per second lately, live sensor only
+    pub rate: Option<f32>,
+}
+
+// readings which died on the way
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FailureStatus {
+    pub count: u64,
+    pub last: Option<String>,
+}
+
+impl Reply {
+    pub fn error(code: ErrorCode, message: &str) -> Self {
+        Reply::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    // one line for the socket
+    pub fn encode(&self, format: Format) -> String {
+        match (format, self) {
+            (Format::Legacy, Reply::Result(outcome)) => {
+                format!("{}|{}", outcome.hexagram, outcome.related)
+            }
+            _ => {
+                let mut value = serde_json::to_value(self).unwrap_or_else(|_| json!({}));
+                value["version"] = json!(VERSION);
+                format!("{}\n", value)
+            }
+        }
+    }
+}
+
+// None means the line is not for us (legacy clients can send anything)
+pub fn parse(line: &str) -> Option<Result<(Command, Format), Reply>> {
+    let line = line.trim();
+    if line == "read" {
+        return Some(Ok((Command::Read(Delivery::Return), Format::Legacy)));
+    }
+    if !line.starts_with('{') {
+        return None;
+    }
+
+    Some(
+        parse_json(line)
+            .map(|command| (command, Format::Json))
+            .map_err(|(code, message)| Reply::error(code, &message)),
+    )
+}
+
+// Err is the code and message of the error reply
+fn parse_json(line: &str) -> Result<Command, (ErrorCode, String)> {
+    let request: Value =
+        serde_json::from_str(line).map_err(|e| (ErrorCode::BadJson, e.to_string()))?;
+
+    match request["version"].as_u64() {
+        Some(VERSION) => {}
+        Some(version) => {
+            return Err((
+                ErrorCode::BadVersion,
+                format!("unsupported version {}, expected {}", version, VERSION),
+            ))
+        }
+        None => return Err((ErrorCode::BadVersion, "missing version".to_string())),
+    }
+
+    match request["command"].as_str() {
+        Some("read") => match request["delivery"].as_str() {
+            None | Some("return") => Ok(Command::Read(Delivery::Return)),
+            Some("connection") => Ok(Command::Read(Delivery::Connection)),
+            Some(delivery) => Err((
+                ErrorCode::BadRequest,
+                format!("unknown delivery \"{}\"", delivery),
+            )),
+        },
+        Some("status") => Ok(Command::Status),
+        Some("cancel") => Ok(Command::Cancel),
+        Some("ping") => Ok(Command::Ping),
+        Some("config") => Ok(Command::Config),
+        Some("subscribe") => Ok(Command::Subscribe),
+        Some("samples") => match &request["secs"] {
+            Value::Null => Ok(Command::Samples(DEFAULT_SAMPLES_SECS)),
+            secs => secs.as_u64().map(Command::Samples).ok_or_else(|| {
+                (
+                    ErrorCode::BadRequest,
+                    "secs must be a whole number".to_string(),
+                )
+            }),
+        },
+        Some(command) => Err((
+            ErrorCode::UnknownCommand,
+            format!("unknown command \"{}\"", command),
+        )),
+        None => Err((ErrorCode::UnknownCommand, "missing command".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> (Command, Format) {
+        match parse(line) {
+            Some(Ok(command)) => command,
+            other => panic!("{}: {:?}", line, other),
+        }
+    }
+
+    // the code of the error reply
+    fn error(line: &str) -> String {
+        match parse(line) {
+            Some(Err(reply)) => serde_json::to_value(&reply).unwrap()["code"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+            other => panic!("{}: {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn legacy_read() {
+        let legacy = (Command::Read(Delivery::Return), Format::Legacy);
+        assert_eq!(command("read"), legacy);
+        assert_eq!(command(" read\r\n"), legacy);
+    }
+
+    #[test]
+    fn legacy_clients_can_send_anything() {
+        for line in ["", "hello", "READ", "read now", "[1]", "42"].iter() {
+            assert!(parse(line).is_none(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn legacy_result_is_two_bit_strings_without_newline() {
+        let cast = Cast::from_hexagrams(&"011111".parse().unwrap(), &"111010".parse().unwrap());
+        let reply = Reply::Result(Outcome::new(&cast, None));
+        assert_eq!(reply.encode(Format::Legacy), "011111|111010");
+
+        let json = reply.encode(Format::Json);
+        assert!(json.ends_with('\n'));
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["version"], 1);
+        assert_eq!(value["reply"], "result");
+        assert_eq!(value["hexagram"], "011111");
+        assert_eq!(value["king_wen"], json!([44, 5]));
+        assert!(value.get("fallback").is_none());
+    }
+
+    #[test]
+    fn json_commands() {
+        let json = |command: Command| (command, Format::Json);
+        assert_eq!(
+            command(r#"{"version": 1, "command": "read"}"#),
+            json(Command::Read(Delivery::Return))
+        );
+        assert_eq!(
+            command(r#"{"version": 1, "command": "read", "delivery": "connection"}"#),
+            json(Command::Read(Delivery::Connection))
+        );
+        assert_eq!(
+            command(r#"{"version": 1, "command": "status"}"#),
+            json(Command::Status)
+        );
+        assert_eq!(
+            command(r#"{"version": 1, "command": "samples"}"#),
+            json(Command::Samples(DEFAULT_SAMPLES_SECS))
+        );
+        assert_eq!(
+            command(r#"{"version": 1, "command": "samples", "secs": 3}"#),
+            json(Command::Samples(3))
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(error("{bad"), "bad_json");
+        assert_eq!(error(r#"{"command": "read"}"#), "bad_version");
+        assert_eq!(error(r#"{"version": 2, "command": "read"}"#), "bad_version");
+        assert_eq!(
+            error(r#"{"version": "1", "command": "read"}"#),
+            "bad_version"
+        );
+        assert_eq!(error(r#"{"version": 1}"#), "unknown_command");
+        assert_eq!(
+            error(r#"{"version": 1, "command": "fly"}"#),
+            "unknown_command"
+        );
+        assert_eq!(
+            error(r#"{"version": 1, "command": "read", "delivery": "mail"}"#),
+            "bad_request"
+        );
+        assert_eq!(
+            error(r#"{"version": 1, "command": "samples", "secs": -1}"#),
+            "bad_request"
+        );
+    }
+
+    #[test]
+    fn error_replies_have_the_version() {
+        let reply = Reply::error(ErrorCode::NotReading, "nothing to cancel");
+        let value: Value = serde_json::from_str(&reply.encode(Format::Legacy)).unwrap();
+        assert_eq!(
+            value,
+            json!({"version": 1, "reply": "error", "code": "not_reading", "message": "nothing to cancel"})
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::hardware::{Disconnected, Sensor};
+
+// capture file format, one sample per line:
+// <milliseconds since the capture start> <pip value>
+// every window starts with a line "# window <milliseconds> <seconds>"
+// other lines starting with # are comments
+
+// plays back a recorded capture
+// every read takes the samples of the next recorded window, so the gaps between
+// windows (pauses in the reading) are skipped and an empty window stays empty
+// captures without windows start every window at the next unread sample
+pub struct ReplaySensor {
+    samples: Vec<(u64, i32)>,
+    // start and seconds of every recorded window
+    windows: Vec<(u64, u64)>,
+    position: usize,
+    window: usize,
+    speed: f64,
+}
+
+impl ReplaySensor {
+    // speed 1.0 is real time, 0.0 is as fast as possible
+    pub fn open(path: &Path, speed: f64) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut samples = vec![];
+        let mut windows = vec![];
+        for (num, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+            let line = line.trim();
+            if let Some(window) = line.strip_prefix(WINDOW) {
+                windows.push(parse_pair(window).ok_or_else(|| {
+                    format!("{}:{}: bad window \"{}\"", path.display(), num + 1, line)
+                })?);
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            samples.push(parse_pair(line).ok_or_else(|| {
+                format!("{}:{}: bad sample \"{}\"", path.display(), num + 1, line)
+            })?);
+        }
+
+        Ok(ReplaySensor {
+            samples,
+            windows,
+            position: 0,
+            window: 0,
+            speed,
+        })
+    }
+
+    // the next recorded window, empty after the last one
+    fn recorded(&mut self, delta: u64) -> Vec<i32> {
+        let (start, secs) = match self.windows.get(self.window) {
+            Some(&window) => window,
+            None => return vec![],
+        };
+        self.window += 1;
+        if secs != delta {
+            println!(
+                "Replay: window {} was {}s, read for {}s",
+                self.window, secs, delta
+            );
+        }
+
+        let end = start + secs * 1000;
+        self.samples
+            .iter()
+            .filter(|(ms, _)| *ms >= start && *ms < end)
+            .map(|(_, value)| *value)
+            .collect()
+    }
+
+    // delta from the next unread sample on
+    fn unmarked(&mut self, delta: u64) -> Vec<i32> {
+        let mut data = vec![];
+        if let Some(&(start, _)) = self.samples.get(self.position) {
+            let end = start + delta * 1000;
+            while let Some(&(ms, value)) = self.samples.get(self.position) {
+                if ms >= end {
+                    break;
+                }
+                data.push(value);
+                self.position += 1;
+            }
+        }
+        data
+    }
+}
+
+impl Sensor for ReplaySensor {
+    fn read(&mut self, delta: u64) -> Result<Vec<i32>, Disconnected> {
+        let data = if self.windows.is_empty() {
+            self.unmarked(delta)
+        } else {
+            self.recorded(delta)
+        };
+
+        if self.speed > 0.0 {
+            thread::sleep(Duration::from_secs(delta).div_f64(self.speed));
+        }
+
+        Ok(data)
+    }
+}
+
+const WINDOW: &str = "# window ";
+
+// "<a> <b>", a sample or a window
+fn parse_pair<T: std::str::FromStr>(line: &str) -> Option<(u64, T)> {
+    let mut parts = line.split_whitespace();
+    let ms = parts.next()?.parse().ok()?;
+    let value = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((ms, value))
+}
+
+// writes everything the inner sensor gives into a new capture file in the directory,
+// one file per reading
+// samples of a window are spread evenly over the window,
+// windows never overlap even if the inner sensor returns early
+pub struct RecordingSensor {
+    inner: Box<dyn Sensor>,
+    file: File,
+    started: Instant,
+    next: u64,
+}
+
+impl RecordingSensor {
+    pub fn create(inner: Box<dyn Sensor>, dir: &Path) -> Result<Self, String> {
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let path = dir.join(format!("{}.capture", stamp));
+        let mut file = fs::create_dir_all(dir)
+            .and_then(|_| File::create(&path))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Err(e) = writeln!(file, "# ioracle capture") {
+            println!("Capture error: {:?}", e);
+        }
+
+        Ok(RecordingSensor {
+            inner,
+            file,
+            started: Instant::now(),
+            next: 0,
+        })
+    }
+}
+
+impl Sensor for RecordingSensor {
+    fn read(&mut self, delta: u64) -> Result<Vec<i32>, Disconnected> {
+        let start = self.next.max(self.started.elapsed().as_millis() as u64);
+        let data = self.inner.read(delta)?;
+
+        let window = delta * 1000;
+        self.next = start + window;
+        if let Err(e) = writeln!(self.file, "{}{} {}", WINDOW, start, delta) {
+            println!("Capture error: {:?}", e);
+        }
+        let count = data.len() as u64;
+        for (i, value) in data.iter().enumerate() {
+            let ms = start + i as u64 * window / count;
+            if let Err(e) = writeln!(self.file, "{} {}", ms, value) {
+                println!("Capture error: {:?}", e);
+                break;
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn malformed(&self) -> u64 {
+        self.inner.malformed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::hardware::Hardware;
+    use crate::iching::Line;
+    use crate::wires;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    fn capture(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}.{}.capture", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn empty_window_stays_empty() {
+        let path = capture(
+            "empty",
+            "# ioracle capture\n\
+             # window 0 2\n\
+             # window 2000 1\n\
+             2000 1\n2500 2\n\
+             # window 3000 2\n\
+             3000 3\n4000 4\n",
+        );
+        let mut sensor = ReplaySensor::open(&path, 0.0).unwrap();
+        assert!(sensor.read(2).unwrap().is_empty());
+        assert_eq!(sensor.read(1).unwrap(), vec![1, 2]);
+        assert_eq!(sensor.read(2).unwrap(), vec![3, 4]);
+        assert!(sensor.read(2).unwrap().is_empty());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn captures_without_windows_read_on() {
+        let path = capture("old", "0 1\n500 2\n1000 3\n5000 4\n");
+        let mut sensor = ReplaySensor::open(&path, 0.0).unwrap();
+        assert_eq!(sensor.read(1).unwrap(), vec![1, 2]);
+        assert_eq!(sensor.read(1).unwrap(), vec![3]);
+        assert_eq!(sensor.read(1).unwrap(), vec![4]);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn bad_window_is_an_error() {
+        let path = capture("bad", "# window 0\n0 1\n");
+        assert!(ReplaySensor::open(&path, 0.0).is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    // a capture as the recorder writes it gives its hexagram again
+    #[test]
+    fn replayed_reading_gives_the_hexagram() {
+        let yang = [500, 600, 500, 600, 500];
+        let yin = [500, 400, 500, 400, 500];
+        // lines 1-3, related 1-3, lines 4-6, related 4-6
+        let windows = [
+            (2, yin),
+            (2, yin),
+            (2, yang),
+            (1, yin),
+            (1, yang),
+            (1, yang),
+            (2, yang),
+            (2, yang),
+            (2, yin),
+            (1, yang),
+            (1, yin),
+            (1, yin),
+        ];
+        let mut contents = "# ioracle capture\n".to_string();
+        let mut start = 0;
+        for (secs, samples) in windows.iter() {
+            contents += &format!("# window {} {}\n", start, secs);
+            for (i, value) in samples.iter().enumerate() {
+                let ms = start + i as u64 * secs * 1000 / samples.len() as u64;
+                contents += &format!("{} {}\n", ms, value);
+            }
+            // the pauses of the reading
+            start += secs * 1000 + 3000;
+        }
+        let path = capture("reading", &contents);
+
+        let mut hw = Hardware::mock(Config::default(), Arc::new(AtomicBool::new(false)));
+        hw.delays = false;
+        hw.leds = None;
+        hw.config.pumps.usage_file = std::env::temp_dir()
+            .join(format!("pumps.replay.{}.usage", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        hw.sensor = Box::new(ReplaySensor::open(&path, 0.0).unwrap());
+
+        let (cast, trace) = wires::reading(&mut hw, &mut |_| {}).unwrap();
+
+        use Line::*;
+        assert_eq!(cast.hexagram().lines(), [Yin, Yin, Yang, Yang, Yang, Yin]);
+        assert_eq!(cast.moving(), vec![1, 3, 4, 6]);
+        assert!(trace.lines.iter().all(|window| window.samples.len() == 5));
+        assert!(trace.fallback.is_none());
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+    }
+}
@@ -0,0 +1,159 @@
+use std::str;
+
+// the teensy prints one frame per sample: "PiPVal: 512\r\n"
+const PREFIX: &str = "PiPVal: ";
+// a frame is a few bytes, anything longer lost its newline
+const MAX_FRAME: usize = 64;
+
+// cuts the serial bytes into frames, whatever the size of the reads
+// a frame split between two reads waits for the rest
+#[derive(Default)]
+pub struct Parser {
+    partial: Vec<u8>,
+    // frames which were not "PiPVal: <number>"
+    pub malformed: u64,
+}
+
+impl Parser {
+    // every complete frame in the bytes, in order
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<i32> {
+        let mut values = vec![];
+        for &byte in bytes {
+            if byte == b'\n' {
+                let frame = std::mem::take(&mut self.partial);
+                // too long was counted when it got too long
+                if frame.len() > MAX_FRAME {
+                    continue;
+                }
+                match parse_frame(&frame) {
+                    Ok(Some(value)) => values.push(value),
+                    Ok(None) => {}
+                    Err(()) => self.malformed += 1,
+                }
+            } else if self.partial.len() < MAX_FRAME {
+                self.partial.push(byte);
+            } else {
+                // counted once, the rest up to the newline is skipped
+                if self.partial.len() == MAX_FRAME {
+                    self.malformed += 1;
+                }
+                self.partial.push(byte);
+                self.partial.truncate(MAX_FRAME + 1);
+            }
+        }
+        values
+    }
+}
+
+// Ok(None) for an empty line
+fn parse_frame(frame: &[u8]) -> Result<Option<i32>, ()> {
+    let text = str::from_utf8(frame).map_err(|_| ())?;
+    let text = text.trim_end_matches('\r');
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    text.strip_prefix(PREFIX)
+        .and_then(|value| value.parse::<i32>().ok())
+        .map(Some)
+        .ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn frame_split_between_feeds() {
+        let mut parser = Parser::default();
+        assert!(parser.feed(b"PiPV").is_empty());
+        assert!(parser.feed(b"al: 5").is_empty());
+        assert!(parser.feed(b"12\r").is_empty());
+        assert_eq!(parser.feed(b"\n"), vec![512]);
+        assert_eq!(parser.malformed, 0);
+    }
+
+    #[test]
+    fn several_frames_in_one_feed() {
+        let mut parser = Parser::default();
+        let values = parser.feed(b"PiPVal: 1\r\nPiPVal: -2\r\nPiPVal: 3\r\nPiPVal: 4");
+        assert_eq!(values, vec![1, -2, 3]);
+        assert_eq!(parser.feed(b"\r\n"), vec![4]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_malformed() {
+        let mut parser = Parser::default();
+        assert_eq!(parser.feed(b"PiPVal: \xff\xfe\r\nPiPVal: 7\r\n"), vec![7]);
+        assert_eq!(parser.malformed, 1);
+    }
+
+    #[test]
+    fn overlong_frame_counted_once() {
+        let mut parser = Parser::default();
+        let long = vec![b'x'; MAX_FRAME * 3];
+        assert!(parser.feed(&long).is_empty());
+        assert!(parser.feed(&long).is_empty());
+        assert_eq!(parser.feed(b"\r\nPiPVal: 9\r\n"), vec![9]);
+        assert_eq!(parser.malformed, 1);
+    }
+
+    #[test]
+    fn empty_lines_are_not_malformed() {
+        let mut parser = Parser::default();
+        assert_eq!(parser.feed(b"\r\n\n\r\nPiPVal: 3\r\n\r\n"), vec![3]);
+        assert_eq!(parser.malformed, 0);
+    }
+
+    #[test]
+    fn empty_line_is_none_not_malformed() {
+        assert_eq!(parse_frame(b""), Ok(None));
+        assert_eq!(parse_frame(b"\r"), Ok(None));
+        assert_eq!(parse_frame(b"PiPVal: 12\r"), Ok(Some(12)));
+        assert_eq!(parse_frame(b"PiPVal: \r"), Err(()));
+        assert_eq!(parse_frame(b"PiPVal: 1x\r"), Err(()));
+        assert_eq!(parse_frame(b"512\r"), Err(()));
+    }
+
+    // bytes which look a bit like the teensy on a bad day
+    fn noise(rng: &mut StdRng) -> Vec<u8> {
+        let mut bytes = vec![];
+        while bytes.len() < 2000 {
+            match rng.gen_range(0..6) {
+                0 => bytes.extend(format!("PiPVal: {}\r\n", rng.gen::<i16>()).bytes()),
+                1 => bytes.extend(b"\r\n"),
+                2 => bytes.push(b'\n'),
+                3 => bytes.extend(vec![b'a'; rng.gen_range(0..3 * MAX_FRAME)]),
+                4 => bytes.extend(b"PiPVal: "),
+                _ => bytes.extend((0..rng.gen_range(0..20)).map(|_| rng.gen::<u8>())),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn chunking_does_not_change_the_frames() {
+        let mut rng = StdRng::seed_from_u64(64);
+        for _ in 0..200 {
+            let bytes = noise(&mut rng);
+
+            let mut whole = Parser::default();
+            let expected = whole.feed(&bytes);
+
+            let mut chunked = Parser::default();
+            let mut values = vec![];
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(rng.gen_range(0..=rest.len().min(100)));
+                values.extend(chunked.feed(chunk));
+                rest = tail;
+            }
+
+            assert_eq!(values, expected);
+            assert_eq!(chunked.malformed, whole.malformed);
+            assert_eq!(chunked.partial, whole.partial);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::events::{Event, Subscribers};
+use crate::hardware::Pins;
+
+// forces outputs low when they stay high too long
+// runs in its own thread, whatever happens to the reading
+#[derive(Clone)]
+pub struct Watchdog {
+    shared: Arc<(Mutex<HashMap<u8, Raised>>, Condvar)>,
+    config: config::Watchdog,
+}
+
+// when the pin went high and when it must be low
+#[derive(Clone, Copy)]
+struct Raised {
+    at: Instant,
+    deadline: Instant,
+}
+
+impl Watchdog {
+    pub fn start(
+        config: &config::Watchdog,
+        mut pins: Box<dyn Pins + Send>,
+        subscribers: Subscribers,
+    ) -> Self {
+        let watchdog = Watchdog {
+            shared: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            config: config.clone(),
+        };
+
+        let shared = watchdog.shared.clone();
+        thread::spawn(move || loop {
+            for (pin, on) in expired(&shared) {
+                println!(
+                    "Safety: pin {} was high for {}s, forced low",
+                    pin,
+                    on.as_secs()
+                );
+                pins.set_low(pin);
+                subscribers.publish(Event::Safety {
+                    pin,
+                    on_secs: on.as_secs(),
+                });
+            }
+        });
+
+        watchdog
+    }
+
+    pub fn raised(&self, pin: u8) {
+        let now = Instant::now();
+        let raised = Raised {
+            at: now,
+            deadline: now + Duration::from_secs(self.config.max_on(pin)),
+        };
+        let (raised_pins, wake) = &*self.shared;
+        if let Ok(mut raised_pins) = raised_pins.lock() {
+            raised_pins.entry(pin).or_insert(raised);
+            wake.notify_one();
+        }
+    }
+
+    pub fn lowered(&self, pin: u8) {
+        let (raised_pins, wake) = &*self.shared;
+        if let Ok(mut raised_pins) = raised_pins.lock() {
+            raised_pins.remove(&pin);
+            wake.notify_one();
+        }
+    }
+}
+
+// sleep until the first deadline, then take the pins which are over it
+fn expired(shared: &(Mutex<HashMap<u8, Raised>>, Condvar)) -> Vec<(u8, Duration)> {
+    let (raised_pins, wake) = shared;
+    let mut raised_pins = match raised_pins.lock() {
+        Ok(raised_pins) => raised_pins,
+        Err(_) => {
+            thread::sleep(Duration::from_secs(1));
+            return vec![];
+        }
+    };
+
+    loop {
+        let now = Instant::now();
+        let over = raised_pins
+            .iter()
+            .filter(|(_, raised)| raised.deadline <= now)
+            .map(|(pin, raised)| (*pin, now - raised.at))
+            .collect::<Vec<(u8, Duration)>>();
+        if !over.is_empty() {
+            for (pin, _) in &over {
+                raised_pins.remove(pin);
+            }
+            return over;
+        }
+
+        let first = raised_pins.values().map(|raised| raised.deadline).min();
+        raised_pins = match first {
+            Some(deadline) => match wake.wait_timeout(raised_pins, deadline - now) {
+                Ok((raised_pins, _)) => raised_pins,
+                Err(_) => return vec![],
+            },
+            None => match wake.wait(raised_pins) {
+                Ok(raised_pins) => raised_pins,
+                Err(_) => return vec![],
+            },
+        };
+    }
+}
+
+// pins which tell the watchdog about every change
+pub struct WatchedPins {
+    inner: Box<dyn Pins>,
+    watchdog: Watchdog,
+}
+
+impl WatchedPins {
+    pub fn new(inner: Box<dyn Pins>, watchdog: Watchdog) -> Self {
+        WatchedPins { inner, watchdog }
+    }
+}
+
+impl Pins for WatchedPins {
+    fn set_high(&mut self, pin: u8) {
+        self.inner.set_high(pin);
+        self.watchdog.raised(pin);
+    }
+
+    fn set_low(&mut self, pin: u8) {
+        self.inner.set_low(pin);
+        self.watchdog.lowered(pin);
+    }
+
+    // a hung script gets its pin forced low too
+    fn script_started(&mut self, pin: u8) {
+        self.inner.script_started(pin);
+        self.watchdog.raised(pin);
+    }
+
+    fn script_ended(&mut self, pin: u8) {
+        self.inner.script_ended(pin);
+        self.watchdog.lowered(pin);
+    }
+}
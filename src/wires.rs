@@ -1,36 +1,80 @@
 use rand::distributions::{Distribution, Uniform};
-use rppal::gpio::Gpio;
 use rs_ws281x::{ChannelBuilder, Controller, ControllerBuilder, StripType};
-use serialport::prelude::*;
-use std::fs::{File, OpenOptions};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
 use std::io::{prelude::*, SeekFrom};
-use std::time::{Duration, SystemTime};
-use std::{process, thread};
-
-const PUMP_FILE: &str = "/tmp/pumps.usage";
-const LEDS_IN_LINE: i32 = 144;
-
-const DEFAULT_COLOUR: &str = "rgb(51, 0, 180)";
-const LI_COLOUR: &str = "rgb(230, 4, 211)";
-const HEAVEN_COLOUR: &str = "rgb(224, 4, 235)";
-const CLOUD_COLOUR: &str = "rgb(255, 2, 14)";
-const SUN_COLOUR: &str = "rgb(255, 109, 0)";
-const WIND_COLOUR: &str = "rgb(121, 255, 0)";
-const THUNDER_COLOUR: &str = "rgb(255, 53, 6)";
-const WATER_COLOUR: &str = "rgb(38, 2, 255)";
-const MOUNTAIN_COLOUR: &str = "rgb(14, 255, 232)";
-const EARTH_COLOUR: &str = "rgb(0, 0, 0)";
+use std::path::Path;
+
+use crate::config::{self, FallbackMethod};
+use crate::decision;
+use crate::events::Event;
+use crate::fallback::{self, Fallback};
+use crate::hardware::{Disconnected, Hardware, Leds};
+use crate::iching::{Cast, Hexagram, Line, Trigram};
+
+// one time window of the sensor and the line we got from it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Window {
+    pub samples: Vec<i32>,
+    // the gain this window got, older journals didn't keep it
+    #[serde(default = "unity")]
+    pub m: f32,
+    pub maxima: usize,
+    pub minima: usize,
+    pub line: Line,
+    // frames of the window the sensor couldn't read
+    #[serde(default)]
+    pub malformed: u64,
+}
+
+fn unity() -> f32 {
+    1.0
+}
+
+// what the trigram made the installation do
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reaction {
+    pub trigram: String,
+    pub actions: Vec<String>,
+}
+
+// everything we saw during the reading
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Trace {
+    pub lines: Vec<Window>,
+    pub related_lines: Vec<Window>,
+    pub reactions: Vec<Reaction>,
+    // the sensor gave up on the way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
+}
+
+// the reading was stopped before the result
+#[derive(Debug)]
+pub enum Stopped {
+    Cancelled,
+    // what happened to the sensor
+    Disconnected(String),
+    // a window had no usable data and the fallback is off
+    NoData(String),
+}
+
+impl From<Disconnected> for Stopped {
+    fn from(error: Disconnected) -> Self {
+        Stopped::Disconnected(error.0)
+    }
+}
 
 // LEDs controller
-pub fn build_controller(brightness: u8) -> Option<Controller> {
+pub fn build_controller(config: &config::Leds, brightness: u8) -> Option<Controller> {
     match ControllerBuilder::new()
-        .freq(800_000)
-        .dma(10)
+        .freq(config.freq)
+        .dma(config.dma)
         .channel(
             0,
             ChannelBuilder::new()
-                .pin(12)
-                .count(6 * LEDS_IN_LINE)
+                .pin(config.yao_pin)
+                .count(6 * config.leds_in_line)
                 .strip_type(StripType::Ws2811Rgb)
                 .brightness(brightness)
                 .build(),
@@ -38,8 +82,8 @@ pub fn build_controller(brightness: u8) -> Option<Controller> {
         .channel(
             1,
             ChannelBuilder::new()
-                .pin(13)
-                .count(3 * LEDS_IN_LINE)
+                .pin(config.li_pin)
+                .count(3 * config.leds_in_line)
                 .strip_type(StripType::Ws2811Rgb)
                 .brightness(brightness)
                 .build(),
@@ -52,20 +96,21 @@ pub fn build_controller(brightness: u8) -> Option<Controller> {
 }
 
 // render the line
-pub fn render(l: u8, line_num: i32, controller: &mut Controller, colour: &String) {
+pub fn render(l: Line, line_num: i32, controller: &mut dyn Leds, colour: &String) {
     match l {
-        1 => render_yang(line_num, controller, colour),
-        _ => render_yin(line_num, controller, colour),
+        Line::Yang => render_yang(line_num, controller, colour),
+        Line::Yin => render_yin(line_num, controller, colour),
     }
 }
 
-pub fn render_yin(line_num: i32, controller: &mut Controller, colour: &String) {
+pub fn render_yin(line_num: i32, controller: &mut dyn Leds, colour: &String) {
     let leds = controller.leds_mut(0);
     let (a, b, c) = parse_colour(colour);
 
-    let part = LEDS_IN_LINE / 3;
-    let position = LEDS_IN_LINE * (line_num - 1);
-    for num in position..position + LEDS_IN_LINE {
+    let leds_in_line = leds_in_line(leds);
+    let part = leds_in_line / 3;
+    let position = leds_in_line * (line_num - 1);
+    for num in position..position + leds_in_line {
         if num > position + part && num < position + part * 2 {
             leds[num as usize] = [0, 0, 0, 0];
         } else {
@@ -78,12 +123,13 @@ pub fn render_yin(line_num: i32, controller: &mut Controller, colour: &String) {
     };
 }
 
-pub fn render_yang(line_num: i32, controller: &mut Controller, colour: &String) {
+pub fn render_yang(line_num: i32, controller: &mut dyn Leds, colour: &String) {
     let leds = controller.leds_mut(0);
     let (a, b, c) = parse_colour(colour);
 
-    let position = LEDS_IN_LINE * (line_num - 1);
-    for num in position..position + LEDS_IN_LINE {
+    let leds_in_line = leds_in_line(leds);
+    let position = leds_in_line * (line_num - 1);
+    for num in position..position + leds_in_line {
         leds[num as usize] = [c, a, b, 0];
     }
 
@@ -92,14 +138,19 @@ pub fn render_yang(line_num: i32, controller: &mut Controller, colour: &String)
     };
 }
 
+// six lines on the yao channel
+fn leds_in_line(yao: &[[u8; 4]]) -> i32 {
+    (yao.len() / 6) as i32
+}
+
 // render resting state for the lines
-pub fn render_resting(controller: &mut Controller) {
+pub fn render_resting(controller: &mut dyn Leds, li_colour: &String) {
     let yao = controller.leds_mut(0);
     for num in 0..yao.len() {
         yao[num as usize] = [0, 0, 0, 0];
     }
 
-    let (a, b, c) = parse_colour(&LI_COLOUR.to_string());
+    let (a, b, c) = parse_colour(li_colour);
     let li = controller.leds_mut(1);
     for num in 0..li.len() {
         li[num as usize] = [c, b, a, 0];
@@ -110,7 +161,7 @@ pub fn render_resting(controller: &mut Controller) {
     }
 }
 
-pub fn render_li(controller: &mut Controller) {
+pub fn render_li(controller: &mut dyn Leds) {
     let li = controller.leds_mut(1);
     let mut rng1 = rand::thread_rng();
     let mut rng2 = rand::thread_rng();
@@ -128,8 +179,8 @@ pub fn render_li(controller: &mut Controller) {
     }
 }
 
-pub fn drop_li_to_default(controller: &mut Controller) {
-    let (a, b, c) = parse_colour(&LI_COLOUR.to_string());
+pub fn drop_li_to_default(controller: &mut dyn Leds, li_colour: &String) {
+    let (a, b, c) = parse_colour(li_colour);
     let li = controller.leds_mut(1);
     for num in 0..li.len() {
         li[num as usize] = [c, b, a, 0];
@@ -140,105 +191,6 @@ pub fn drop_li_to_default(controller: &mut Controller) {
     }
 }
 
-pub fn reading_no_led() -> (String, String) {
-    println!("New reading... No leds");
-
-    // let yao = controller.leds_mut(0);
-    // for num in 0..LEDS_IN_LINE * 6 {
-    //     yao[num as usize] = [0, 0, 0, 0];
-    // }
-    // if let Err(e) = controller.render() {
-    //     println!("Yao error while reading: {:?}", e);
-    // };
-
-    //---------------------------------------------------
-    let m = "1".to_string();
-    let b = "500".to_string();
-    let t = "10".to_string();
-    //---------------------------------------------------
-
-    let line1 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line1 = {}", line1);
-    // render(line1, 6, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line2 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line2 = {}", line2);
-    // render(line2, 1, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line3 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line3 = {}", line3);
-    // render(line3, 2, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(2));
-
-    let first = format!("{}{}{}", line1, line2, line3);
-    react_no_leds(&first, 6, 1, 2);
-
-    // special Earth rules
-    // if first == "000" {
-    //     thread::sleep(Duration::from_secs(2));
-    //     render_yin(6, controller, &DEFAULT_COLOUR.to_string());
-    //     render_yin(1, controller, &DEFAULT_COLOUR.to_string());
-    //     render_yin(2, controller, &DEFAULT_COLOUR.to_string());
-    // }
-
-    // get related lines
-    let lr1 = read(1, m.clone(), b.clone(), t.clone());
-    let lr2 = read(1, m.clone(), b.clone(), t.clone());
-    let lr3 = read(1, m.clone(), b.clone(), t.clone());
-
-    drop_pins();
-    thread::sleep(Duration::from_secs(3));
-    //drop_li_to_default(controller);
-
-    let line4 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line4 = {}", line4);
-    // render(line4, 3, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line5 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line5 = {}", line5);
-    // render(line5, 4, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line6 = read(2, m.clone(), b.clone(), t.clone());
-    println!("line6 = {}", line6);
-    // render(line6, 5, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(2));
-
-    let second = format!("{}{}{}", line4, line5, line6);
-    react_no_leds(&second, 3, 4, 5);
-
-    // special Earth rules
-    // if second == "000" {
-    //     thread::sleep(Duration::from_secs(2));
-    //     render_yin(3, controller, &DEFAULT_COLOUR.to_string());
-    //     render_yin(4, controller, &DEFAULT_COLOUR.to_string());
-    //     render_yin(5, controller, &DEFAULT_COLOUR.to_string());
-    // }
-
-    // get related lines
-    let lr4 = read(1, m.clone(), b.clone(), t.clone());
-    let lr5 = read(1, m.clone(), b.clone(), t.clone());
-    let lr6 = read(1, m.clone(), b.clone(), t.clone());
-
-    drop_pins();
-    //drop_li_to_default(controller);
-
-    let hexagram = format!("{}{}{}{}{}{}", line1, line2, line3, line4, line5, line6);
-    let related_original = format!("{}{}{}{}{}{}", lr1, lr2, lr3, lr4, lr5, lr6);
-    let related = get_related(&hexagram, &related_original);
-
-    (hexagram, related)
-}
-
 // this is the main reading function
 // first we read 3 lines with bias, threshold, multy parameters
 // then we react depending on a first trigram
@@ -247,164 +199,283 @@ pub fn reading_no_led() -> (String, String) {
 // then we react depending on a second trigram
 // then we read 3 related lines with a small time window
 // from all this data we can build the hexagram and the related hexagram
-pub fn reading(controller: &mut Controller) -> (String, String) {
+// every line and reaction goes to events as soon as we have it
+// cancelled reading or lost sensor leaves the pins down and the LEDs dark
+pub fn reading(hw: &mut Hardware, events: &mut dyn FnMut(Event)) -> Result<(Cast, Trace), Stopped> {
+    let result = read_all(hw, events);
+    if let Err(stopped) = &result {
+        match stopped {
+            Stopped::Cancelled => println!("Reading cancelled"),
+            Stopped::Disconnected(error) => println!("Sensor disconnected: {}", error),
+            Stopped::NoData(reason) => println!("No sensor data: {}", reason),
+        }
+        drop_pins(hw);
+        blank(hw);
+    }
+
+    result
+}
+
+fn read_all(hw: &mut Hardware, events: &mut dyn FnMut(Event)) -> Result<(Cast, Trace), Stopped> {
     println!("New reading...");
+    let mut trace = Trace::default();
 
-    let yao = controller.leds_mut(0);
-    for num in 0..LEDS_IN_LINE * 6 {
-        yao[num as usize] = [0, 0, 0, 0];
+    if let Some(controller) = hw.leds.as_deref_mut() {
+        let yao = controller.leds_mut(0);
+        for led in yao.iter_mut() {
+            *led = [0, 0, 0, 0];
+        }
+        if let Err(e) = controller.render() {
+            println!("Yao error while reading: {:?}", e);
+        };
     }
-    if let Err(e) = controller.render() {
-        println!("Yao error while reading: {:?}", e);
-    };
 
-    //---------------------------------------------------
-    let m = "1".to_string();
-    let b = "500".to_string();
-    let t = "10".to_string();
-    //---------------------------------------------------
+    let after_line = hw.config.timing.after_line;
+    let after_trigram = hw.config.timing.after_trigram;
 
-    let line1 = read(2, m.clone(), b.clone(), t.clone());
+    let line1 = read_primary(hw, 1, &mut trace)?;
     println!("line1 = {}", line1);
-    render(line1, 6, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line2 = read(2, m.clone(), b.clone(), t.clone());
+    show(hw, line1, 6);
+    events(Event::Line {
+        line: 1,
+        value: line1,
+    });
+    hw.pause(after_line);
+
+    let line2 = read_primary(hw, 2, &mut trace)?;
     println!("line2 = {}", line2);
-    render(line2, 1, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line3 = read(2, m.clone(), b.clone(), t.clone());
+    show(hw, line2, 1);
+    events(Event::Line {
+        line: 2,
+        value: line2,
+    });
+    hw.pause(after_line);
+
+    let line3 = read_primary(hw, 3, &mut trace)?;
     println!("line3 = {}", line3);
-    render(line3, 2, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(2));
-
-    let first = format!("{}{}{}", line1, line2, line3);
-    react(controller, &first, 6, 1, 2);
+    show(hw, line3, 2);
+    events(Event::Line {
+        line: 3,
+        value: line3,
+    });
+    hw.pause(after_trigram);
+
+    go_on(hw)?;
+    let first = Trigram::new([line1, line2, line3]);
+    let reaction = react(hw, first, 6, 1, 2);
+    events(Event::Reaction(reaction.clone()));
+    trace.reactions.push(reaction);
 
     // special Earth rules
-    if first == "000" {
-        thread::sleep(Duration::from_secs(2));
-        render_yin(6, controller, &DEFAULT_COLOUR.to_string());
-        render_yin(1, controller, &DEFAULT_COLOUR.to_string());
-        render_yin(2, controller, &DEFAULT_COLOUR.to_string());
+    if first == Trigram::Earth {
+        earth(hw, 6, 1, 2);
     }
 
     // get related lines
-    let lr1 = read(1, m.clone(), b.clone(), t.clone());
-    let lr2 = read(1, m.clone(), b.clone(), t.clone());
-    let lr3 = read(1, m.clone(), b.clone(), t.clone());
+    let lr1 = read_related(hw, 1, line1, &mut trace, events)?;
+    let lr2 = read_related(hw, 2, line2, &mut trace, events)?;
+    let lr3 = read_related(hw, 3, line3, &mut trace, events)?;
 
-    drop_pins();
-    thread::sleep(Duration::from_secs(3));
+    drop_pins(hw);
+    hw.pause(hw.config.timing.between_trigrams);
     //drop_li_to_default(controller);
 
-    let line4 = read(2, m.clone(), b.clone(), t.clone());
+    let line4 = read_primary(hw, 4, &mut trace)?;
     println!("line4 = {}", line4);
-    render(line4, 3, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line5 = read(2, m.clone(), b.clone(), t.clone());
+    show(hw, line4, 3);
+    events(Event::Line {
+        line: 4,
+        value: line4,
+    });
+    hw.pause(after_line);
+
+    let line5 = read_primary(hw, 5, &mut trace)?;
     println!("line5 = {}", line5);
-    render(line5, 4, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(3));
-
-    let line6 = read(2, m.clone(), b.clone(), t.clone());
+    show(hw, line5, 4);
+    events(Event::Line {
+        line: 5,
+        value: line5,
+    });
+    hw.pause(after_line);
+
+    let line6 = read_primary(hw, 6, &mut trace)?;
     println!("line6 = {}", line6);
-    render(line6, 5, controller, &DEFAULT_COLOUR.to_string());
-    // render_li(controller);
-    thread::sleep(Duration::from_secs(2));
-
-    let second = format!("{}{}{}", line4, line5, line6);
-    react(controller, &second, 3, 4, 5);
+    show(hw, line6, 5);
+    events(Event::Line {
+        line: 6,
+        value: line6,
+    });
+    hw.pause(after_trigram);
+
+    go_on(hw)?;
+    let second = Trigram::new([line4, line5, line6]);
+    let reaction = react(hw, second, 3, 4, 5);
+    events(Event::Reaction(reaction.clone()));
+    trace.reactions.push(reaction);
 
     // special Earth rules
-    if second == "000" {
-        thread::sleep(Duration::from_secs(2));
-        render_yin(3, controller, &DEFAULT_COLOUR.to_string());
-        render_yin(4, controller, &DEFAULT_COLOUR.to_string());
-        render_yin(5, controller, &DEFAULT_COLOUR.to_string());
+    if second == Trigram::Earth {
+        earth(hw, 3, 4, 5);
     }
 
     // get related lines
-    let lr4 = read(1, m.clone(), b.clone(), t.clone());
-    let lr5 = read(1, m.clone(), b.clone(), t.clone());
-    let lr6 = read(1, m.clone(), b.clone(), t.clone());
+    let lr4 = read_related(hw, 4, line4, &mut trace, events)?;
+    let lr5 = read_related(hw, 5, line5, &mut trace, events)?;
+    let lr6 = read_related(hw, 6, line6, &mut trace, events)?;
 
-    drop_pins();
+    drop_pins(hw);
     //drop_li_to_default(controller);
 
-    let hexagram = format!("{}{}{}{}{}{}", line1, line2, line3, line4, line5, line6);
-    let related_original = format!("{}{}{}{}{}{}", lr1, lr2, lr3, lr4, lr5, lr6);
+    let hexagram = Hexagram::new([line1, line2, line3, line4, line5, line6]);
+    let related_original = Hexagram::new([lr1, lr2, lr3, lr4, lr5, lr6]);
     let related = get_related(&hexagram, &related_original);
 
-    (hexagram, related)
+    Ok((Cast::from_hexagrams(&hexagram, &related), trace))
 }
 
-// read pip data from the serial port
-// install arduino ide + teense support to read from serial port on rpi
-pub fn read_the_pip(delta: u64) -> Vec<i32> {
-    let s = SerialPortSettings {
-        baud_rate: 9600,
-        data_bits: DataBits::Eight,
-        flow_control: FlowControl::None,
-        parity: Parity::None,
-        stop_bits: StopBits::One,
-        timeout: Duration::from_secs(1),
-    };
+// stop between the steps if somebody cancelled the reading
+fn go_on(hw: &Hardware) -> Result<(), Stopped> {
+    if hw.cancelled() {
+        Err(Stopped::Cancelled)
+    } else {
+        Ok(())
+    }
+}
 
-    let mut data: Vec<i32> = vec![];
-    if let Ok(mut port) = serialport::open_with_settings("/dev/ttyACM0", &s) {
-        let mut serial_buf: Vec<u8> = vec![0; 512];
-        let start = SystemTime::now();
-        loop {
-            if let Ok(d) = start.elapsed() {
-                if d > Duration::from_secs(delta) {
-                    break;
-                };
-            }
-            match port.read(serial_buf.as_mut_slice()) {
-                Ok(t) => {
-                    // println!("Pip val: {}", get_val(&serial_buf[..t]));
-                    data.push(get_val(&serial_buf[..t]));
-                }
-                Err(e) => eprintln!("{:?}", e),
+// both strips dark
+pub fn blank(hw: &mut Hardware) {
+    if let Some(controller) = hw.leds.as_deref_mut() {
+        for channel in 0..2 {
+            for led in controller.leds_mut(channel).iter_mut() {
+                *led = [0, 0, 0, 0];
             }
         }
+        if let Err(e) = controller.render() {
+            println!("LEDs error while blanking: {:?}", e);
+        };
     }
+}
 
-    data
+// show the line if we have LEDs
+fn show(hw: &mut Hardware, l: Line, line_num: i32) {
+    if let Some(controller) = hw.leds.as_deref_mut() {
+        render(l, line_num, controller, &hw.config.colours.default);
+        // render_li(controller);
+    }
 }
 
-pub fn get_val(buf: &[u8]) -> i32 {
-    let mut output = 0;
-    let serial_data = std::str::from_utf8(buf).unwrap();
-    if let Some(i) = serial_data.find("PiPVal: ") {
-        let s = &serial_data[i + 8..];
-        if let Some(j) = s.find("\r") {
-            let str_value = &s[..j];
-            if let Ok(value) = str_value.parse::<i32>() {
-                output = value;
-            }
+// earth trigram goes dark, so draw it again after a while
+fn earth(hw: &mut Hardware, l1: i32, l2: i32, l3: i32) {
+    if hw.leds.is_some() {
+        hw.pause(hw.config.timing.earth);
+    }
+    if let Some(controller) = hw.leds.as_deref_mut() {
+        let colour = &hw.config.colours.default;
+        render_yin(l1, controller, colour);
+        render_yin(l2, controller, colour);
+        render_yin(l3, controller, colour);
+    }
+}
+
+// read the line and keep the window
+// a window cut by cancel is not a line, a window without usable data is None
+fn read_line(
+    hw: &mut Hardware,
+    position: usize,
+    delta: u64,
+    windows: &mut Vec<Window>,
+) -> Result<Option<Line>, Stopped> {
+    go_on(hw)?;
+    let window = read(hw, position, delta)?;
+    go_on(hw)?;
+    let line = match fallback::usable(&window.samples, &hw.config.fallback) {
+        Ok(()) => Some(window.line),
+        Err(reason) => {
+            println!("No usable data: {}", reason);
+            None
         }
+    };
+    windows.push(window);
+    Ok(line)
+}
+
+// from the first window without data on, the lines are cast
+fn fall_back(hw: &Hardware, failed: fallback::Read, trace: &mut Trace) -> Result<(), Stopped> {
+    let method = hw.config.fallback.method;
+    let reason = format!("no usable data for {}", failed);
+    if method == FallbackMethod::Off {
+        return Err(Stopped::NoData(reason));
     }
+    println!("Falling back to {} from {}", method.name(), failed);
+    trace.fallback = Some(Fallback::new(method, failed, reason));
+    Ok(())
+}
 
-    output
+// a line of the hexagram, the sensor window keeps the rhythm even when it's cast
+fn read_primary(hw: &mut Hardware, position: usize, trace: &mut Trace) -> Result<Line, Stopped> {
+    let delta = hw.config.sensor.line_secs;
+    if trace.fallback.is_none() {
+        if let Some(line) = read_line(hw, position, delta, &mut trace.lines)? {
+            return Ok(line);
+        }
+        fall_back(hw, fallback::Read::Line(position), trace)?;
+    } else {
+        hw.pause(delta);
+        go_on(hw)?;
+    }
+
+    Ok(trace
+        .fallback
+        .as_mut()
+        .map_or(Line::Yin, |fallback| fallback.line(position)))
 }
 
-// read the pip data with timer and parameters
-pub fn read(delta: u64, m: String, b: String, t: String) -> u8 {
-    let _m: f32 = m.parse().unwrap_or_else(|_| 1.0);
-    let b: f32 = b.parse().unwrap_or_else(|_| 0.0);
-    let t: f32 = t.parse().unwrap_or_else(|_| 0.0);
+// quick line for the related hexagram
+fn read_related(
+    hw: &mut Hardware,
+    position: usize,
+    line: Line,
+    trace: &mut Trace,
+    events: &mut dyn FnMut(Event),
+) -> Result<Line, Stopped> {
+    let delta = hw.config.sensor.related_secs;
+    let mut related = None;
+    if trace.fallback.is_none() {
+        related = read_line(hw, position, delta, &mut trace.related_lines)?;
+        if related.is_none() {
+            fall_back(hw, fallback::Read::Related(position), trace)?;
+        }
+    } else {
+        hw.pause(delta);
+        go_on(hw)?;
+    }
 
-    let data = read_the_pip(delta);
+    let related = match (related, trace.fallback.as_mut()) {
+        (Some(related), _) => related,
+        (None, Some(fallback)) => fallback.related(position, line),
+        (None, None) => line.flip(),
+    };
+    events(Event::Related {
+        line: position,
+        value: related,
+    });
+    Ok(related)
+}
+
+// read the pip data with timer and parameters
+// position is the line, 1 is the bottom
+pub fn read(hw: &mut Hardware, position: usize, delta: u64) -> Result<Window, Disconnected> {
+    let m = hw.config.sensor.m(position);
+    let b = hw.config.sensor.b;
+    let t = hw.config.sensor.t;
+
+    let before = hw.sensor.malformed();
+    let data = hw.sensor.read(delta)?;
     println!("data: {:?}", data);
+    let malformed = hw.sensor.malformed() - before;
+    if malformed > 0 {
+        println!("{} malformed frames", malformed);
+    }
 
     let mut min = 0;
     if let Some(m) = data.iter().min() {
@@ -418,285 +489,159 @@ pub fn read(delta: u64, m: String, b: String, t: String) -> u8 {
     };
     println!("max: {}", max);
 
-    let n_data = data.iter().map(|&i| i as f32 - b).collect::<Vec<f32>>();
+    let n_data = data
+        .iter()
+        .map(|&i| (i as f32 - b) * m)
+        .collect::<Vec<f32>>();
     println!("n_data = {:?}", n_data);
 
-    let mut mins: Vec<f32> = vec![];
-    let mut maxs: Vec<f32> = vec![];
-    for i in n_data.windows(3) {
-        if i[1] > i[0] && i[1] > i[2] && i[1] > t {
-            // println!("local max extremum = {:?}", i[1]);
-            maxs.push(i[1]);
-        }
-        if i[1] < i[0] && i[1] < i[2] && i[1].abs() > t {
-            // println!("local min extremum = {:?}", i[1]);
-            mins.push(i[1]);
-        }
-        // println!("windows iter = {:?}", i);
-    }
-
-    // println!("mins = {:?}", mins);
-    // println!("mins len = {:?}", mins.len());
-    // println!("maxs = {:?}", maxs);
-    // println!("maxs len = {:?}", maxs.len());
-
-    if maxs.len() > mins.len() {
-        1
-    } else {
-        0
-    }
+    // the extrema go to the journal whatever decides the line
+    let (maxima, minima) = decision::extrema(&n_data, t);
+    let signal = decision::Signal {
+        raw: &data,
+        normalised: &n_data,
+        rate: data.len() as f32 / delta as f32,
+    };
+    let line = decision::method(&hw.config.sensor).decide(&signal);
+    println!("{:?} by {}", line, hw.config.sensor.method.name());
+
+    Ok(Window {
+        samples: data,
+        m,
+        maxima,
+        minima,
+        line,
+        malformed,
+    })
 }
 
-pub fn react_no_leds(trigram: &String, l1: i32, l2: i32, l3: i32) {
-    match trigram.as_str() {
-        // Heaven
-        "111" => {
-            pin_on(5);
-            // render_yang(l1, controller, &HEAVEN_COLOUR.to_string());
-            // render_yang(l2, controller, &HEAVEN_COLOUR.to_string());
-            // render_yang(l3, controller, &HEAVEN_COLOUR.to_string());
+// here we react on tirgram with the hardware
+pub fn react(hw: &mut Hardware, trigram: Trigram, l1: i32, l2: i32, l3: i32) -> Reaction {
+    println!("--------> {} ({})", trigram.name(), trigram.chinese_name());
+
+    let pins = hw.config.pins.clone();
+    let colours = hw.config.colours.clone();
+    let mut actions = vec![];
+    let colour = match trigram {
+        Trigram::Heaven => {
+            actions.push(pin_on(hw, pins.heaven));
+            colours.heaven
         }
-        // Cloud
-        "110" => {
-            pin_on(8);
-            // render_yang(l1, controller, &CLOUD_COLOUR.to_string());
-            // render_yang(l2, controller, &CLOUD_COLOUR.to_string());
-            // render_yin(l3, controller, &CLOUD_COLOUR.to_string());
+        Trigram::Cloud => {
+            actions.push(pin_on(hw, pins.cloud));
+            colours.cloud
         }
-        // Sun
-        "101" => {
-            shell_fire();
-            // render_yang(l1, controller, &SUN_COLOUR.to_string());
-            // render_yin(l2, controller, &SUN_COLOUR.to_string());
-            // render_yang(l3, controller, &SUN_COLOUR.to_string());
+        Trigram::Sun => {
+            actions.push(shell_fire(hw));
+            colours.sun
         }
-        // Wind
-        "011" => {
-            pin_on(20);
-            // render_yin(l1, controller, &WIND_COLOUR.to_string());
-            // render_yang(l2, controller, &WIND_COLOUR.to_string());
-            // render_yang(l3, controller, &WIND_COLOUR.to_string());
+        Trigram::Wind => {
+            actions.push(pin_on(hw, pins.wind));
+            colours.wind
         }
-        // Thunder
-        "100" => {
-            play_sound("thunder.wav".to_string());
-            // render_yang(l1, controller, &THUNDER_COLOUR.to_string());
-            // render_yin(l2, controller, &THUNDER_COLOUR.to_string());
-            // render_yin(l3, controller, &THUNDER_COLOUR.to_string());
+        Trigram::Thunder => {
+            actions.push(play_sound(hw, "thunder.wav".to_string()));
+            colours.thunder
         }
-        // Water
-        "010" => {
-            pin_on(6);
-            // render_yin(l1, controller, &WATER_COLOUR.to_string());
-            // render_yang(l2, controller, &WATER_COLOUR.to_string());
-            // render_yin(l3, controller, &WATER_COLOUR.to_string());
+        Trigram::Water => {
+            actions.push(pin_on(hw, pins.water));
+            colours.water
         }
-        // Mountain
-        "001" => {
-            pin_on(7);
-            play_sound("mountain.wav".to_string());
-            // render_yin(l1, controller, &MOUNTAIN_COLOUR.to_string());
-            // render_yin(l2, controller, &MOUNTAIN_COLOUR.to_string());
-            // render_yang(l3, controller, &MOUNTAIN_COLOUR.to_string());
+        Trigram::Mountain => {
+            actions.push(pin_on(hw, pins.mountain));
+            actions.push(play_sound(hw, "mountain.wav".to_string()));
+            colours.mountain
         }
-        // Earth
-        "000" => {
-            play_sound("mountain.wav".to_string());
-            // render_yin(l1, controller, &EARTH_COLOUR.to_string());
-            // render_yin(l2, controller, &EARTH_COLOUR.to_string());
-            // render_yin(l3, controller, &EARTH_COLOUR.to_string());
+        Trigram::Earth => {
+            actions.push(play_sound(hw, "mountain.wav".to_string()));
+            colours.earth
         }
-        // Error
-        _ => {}
-    }
-}
+    };
 
-// here we react on tirgram with the hardware
-pub fn react(controller: &mut Controller, trigram: &String, l1: i32, l2: i32, l3: i32) {
-    match trigram.as_str() {
-        // Heaven
-        "111" => {
-            pin_on(5);
-            render_yang(l1, controller, &HEAVEN_COLOUR.to_string());
-            render_yang(l2, controller, &HEAVEN_COLOUR.to_string());
-            render_yang(l3, controller, &HEAVEN_COLOUR.to_string());
-        }
-        // Cloud
-        "110" => {
-            pin_on(8);
-            render_yang(l1, controller, &CLOUD_COLOUR.to_string());
-            render_yang(l2, controller, &CLOUD_COLOUR.to_string());
-            render_yin(l3, controller, &CLOUD_COLOUR.to_string());
-        }
-        // Sun
-        "101" => {
-            shell_fire();
-            render_yang(l1, controller, &SUN_COLOUR.to_string());
-            render_yin(l2, controller, &SUN_COLOUR.to_string());
-            render_yang(l3, controller, &SUN_COLOUR.to_string());
-        }
-        // Wind
-        "011" => {
-            pin_on(20);
-            render_yin(l1, controller, &WIND_COLOUR.to_string());
-            render_yang(l2, controller, &WIND_COLOUR.to_string());
-            render_yang(l3, controller, &WIND_COLOUR.to_string());
-        }
-        // Thunder
-        "100" => {
-            play_sound("thunder.wav".to_string());
-            render_yang(l1, controller, &THUNDER_COLOUR.to_string());
-            render_yin(l2, controller, &THUNDER_COLOUR.to_string());
-            render_yin(l3, controller, &THUNDER_COLOUR.to_string());
-        }
-        // Water
-        "010" => {
-            pin_on(6);
-            render_yin(l1, controller, &WATER_COLOUR.to_string());
-            render_yang(l2, controller, &WATER_COLOUR.to_string());
-            render_yin(l3, controller, &WATER_COLOUR.to_string());
+    // trigram lines in the trigram colour
+    if let Some(controller) = hw.leds.as_deref_mut() {
+        for (l, line_num) in trigram.lines().iter().zip([l1, l2, l3].iter()) {
+            render(*l, *line_num, controller, &colour);
         }
-        // Mountain
-        "001" => {
-            pin_on(7);
-            play_sound("mountain.wav".to_string());
-            render_yin(l1, controller, &MOUNTAIN_COLOUR.to_string());
-            render_yin(l2, controller, &MOUNTAIN_COLOUR.to_string());
-            render_yang(l3, controller, &MOUNTAIN_COLOUR.to_string());
-        }
-        // Earth
-        "000" => {
-            play_sound("mountain.wav".to_string());
-            render_yin(l1, controller, &EARTH_COLOUR.to_string());
-            render_yin(l2, controller, &EARTH_COLOUR.to_string());
-            render_yin(l3, controller, &EARTH_COLOUR.to_string());
-        }
-        // Error
-        _ => {}
+    }
+
+    Reaction {
+        trigram: trigram.name().to_string(),
+        actions,
     }
 }
 
 // turn the pins on and off on rpi model 4
-pub fn pin_on(pin: u8) {
+// some pins are run by scripts with their own timing
+pub fn pin_on(hw: &mut Hardware, pin: u8) -> String {
     println!("--------> pin {}: on", pin);
 
-    if pin == 8 {
-        pin8_start();
-        // if let Ok(gpio) = Gpio::new() {
-        //     if let Ok(pin8) = gpio.get(8) {
-        //         let mut pin8 = pin8.into_output();
-        //         pin8.set_high();
-        //         thread::sleep(Duration::from_secs(6));
-        //         pin8.set_low();
-        //     }
-        // }
-    } else if pin == 7 {
-        pin7_start();
-        // if let Ok(gpio) = Gpio::new() {
-        //     if let Ok(pin7) = gpio.get(7) {
-        //         let mut pin7 = pin7.into_output();
-        //         pin7.set_high();
-        //         thread::sleep(Duration::from_secs(4));
-        //         pin7.set_low();
-        //     }
-        // }
+    if hw.config.pins.scripted.contains(&pin) {
+        hw.pins.script_started(pin);
+        run_effect(hw, &format!("pin{}", pin));
+        hw.pins.script_ended(pin);
     } else {
-        if let Ok(gpio) = Gpio::new() {
-            if let Ok(pin) = gpio.get(pin) {
-                let mut pin = pin.into_output();
-                pin.set_high();
-            }
-        }
+        hw.pins.set_high(pin);
     }
-    if pin == 6 || pin == 7 || pin == 8 {
-        check_the_pumps();
+    if hw.config.pins.pumps.contains(&pin) {
+        check_the_pumps(
+            Path::new(&hw.config.pumps.usage_file),
+            hw.config.pumps.refill_after,
+        );
     }
-}
 
-pub fn pin_off(pin: u8) {
-    println!("--------> pin {}: off", pin);
-
-    if let Ok(gpio) = Gpio::new() {
-        if let Ok(pin) = gpio.get(pin) {
-            let mut pin = pin.into_output();
-            pin.set_low();
-        }
-    }
-}
-
-pub fn pin7_start() {
-    println!("--------> pin7");
-
-    if let Err(e) = process::Command::new("/ioracle/scripts/pin7.sh").output() {
-        println!("pin7 error: {:?}", e);
-    }
+    format!("pin {}", pin)
 }
 
-pub fn pin8_start() {
-    println!("--------> pin8");
+pub fn pin_off(hw: &mut Hardware, pin: u8) {
+    println!("--------> pin {}: off", pin);
 
-    if let Err(e) = process::Command::new("/ioracle/scripts/pin8.sh").output() {
-        println!("pin8 error: {:?}", e);
-    }
+    hw.pins.set_low(pin);
 }
 
 // experiments on fire launch
-pub fn shell_fire() {
-    println!("--------> fire");
-
-    // if let Err(e) = process::Command::new("/ioracle/scripts/fire.sh").spawn() {
-    //     println!("{:?}", e);
-    // }
-    if let Err(e) = process::Command::new("/ioracle/scripts/fire.sh").output() {
-        println!("fire error: {:?}", e);
-    }
-    // process::Command::new("/ioracle/scripts/fire.sh")
-    //     .output()
-    //     .expect("");
+pub fn shell_fire(hw: &mut Hardware) -> String {
+    run_effect(hw, "fire");
+    "fire".to_string()
 }
 
 // experiments on sounds
-pub fn play_sound(file_name: String) {
-    println!("--------> sound");
-
-    let cmd;
-    if file_name == "thunder.wav" {
-        cmd = "/ioracle/scripts/thunder.sh";
+pub fn play_sound(hw: &mut Hardware, file_name: String) -> String {
+    let sound = if file_name == "thunder.wav" {
+        "thunder"
     } else {
-        cmd = "/ioracle/scripts/mountain.sh";
-    }
-    // process::Command::new(cmd).spawn().expect("");
+        "mountain"
+    };
+    run_effect(hw, sound);
+    sound.to_string()
+}
 
-    if let Err(e) = process::Command::new(cmd).output() {
-        println!("sound error: {:?}", e);
+// a script which didn't run is not a reason to stop the reading
+fn run_effect(hw: &mut Hardware, effect: &str) {
+    if let Err(error) = hw.effects.run(effect) {
+        println!("{} error: {}", effect, error);
     }
 }
 
-pub fn drop_pins() {
+pub fn drop_pins(hw: &mut Hardware) {
     println!("--------> drop pins");
 
-    pin_off(5);
-    // pin_off(8);
-    pin_off(20);
-    pin_off(6);
-    // pin_off(7);
-}
-
-pub fn get_related(h: &String, r: &String) -> String {
-    let mut result = "".to_string();
-    for (x, y) in h.chars().zip(r.chars()) {
-        if x.eq(&y) {
-            if x.eq(&'0') {
-                result = format!("{}1", result);
-            } else {
-                result = format!("{}0", result);
-            }
-        } else {
-            result = format!("{}{}", result, x);
+    for pin in hw.config.pins.dropped.clone() {
+        pin_off(hw, pin);
+    }
+}
+
+// lines which came the same in the quick reading change to the opposite
+pub fn get_related(h: &Hexagram, r: &Hexagram) -> Hexagram {
+    let (h, r) = (h.lines(), r.lines());
+    let mut result = h;
+    for i in 0..6 {
+        if h[i] == r[i] {
+            result[i] = h[i].flip();
         }
     }
 
-    result
+    Hexagram::new(result)
 }
 
 fn parse_colour(colour: &String) -> (u8, u8, u8) {
@@ -723,13 +668,13 @@ fn parse_colour(colour: &String) -> (u8, u8, u8) {
 }
 
 // check the pumps levels
-fn check_the_pumps() {
-    if let Ok(mut file) = OpenOptions::new().read(true).write(true).open(PUMP_FILE) {
+pub fn check_the_pumps(pump_file: &Path, refill_after: i32) {
+    if let Ok(mut file) = OpenOptions::new().read(true).write(true).open(pump_file) {
         let mut contents = String::new();
         if let Ok(_) = file.read_to_string(&mut contents) {
             if let Ok(num) = contents.parse::<i32>() {
                 let mut x = num + 1;
-                if x > 6 {
+                if x > refill_after {
                     send_mail();
                     x = 0;
                 }
@@ -743,7 +688,7 @@ fn check_the_pumps() {
             }
         };
     } else {
-        if let Ok(mut file) = File::create(PUMP_FILE) {
+        if let Ok(mut file) = File::create(pump_file) {
             if let Err(e) = file.write_all(b"1") {
                 println!("{:?}", e);
             };
@@ -751,6 +696,177 @@ fn check_the_pumps() {
     };
 }
 
+// runs since the last refill, None if the pumps never ran
+pub fn pump_usage(pump_file: &Path) -> Option<i32> {
+    fs::read_to_string(pump_file)
+        .ok()
+        .and_then(|contents| contents.parse::<i32>().ok())
+}
+
 fn send_mail() {
     println!("refil the pumps!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::events::Subscribers;
+    use crate::hardware::Effects;
+    use crate::mock::{MockEffects, MockLeds, MockPins, MockSensor};
+    use crate::watchdog::{Watchdog, WatchedPins};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    const YANG: [i32; 5] = [500, 600, 500, 600, 500];
+    const YIN: [i32; 5] = [500, 400, 500, 400, 500];
+
+    // a pumps file of its own for every test
+    fn mock(name: &str) -> Hardware {
+        let mut hw = Hardware::mock(Config::default(), Arc::new(AtomicBool::new(false)));
+        hw.delays = false;
+        hw.config.pumps.usage_file = std::env::temp_dir()
+            .join(format!("pumps.{}.{}.usage", name, std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+        hw
+    }
+
+    #[test]
+    fn scripted_windows_give_the_hexagram() {
+        let mut hw = mock("reading");
+        // lines 1-3, related 1-3, lines 4-6, related 4-6
+        let windows = [
+            YANG, YIN, YANG, YANG, YANG, YANG, YIN, YIN, YANG, YIN, YANG, YIN,
+        ];
+        hw.sensor = Box::new(MockSensor::scripted(
+            windows.iter().map(|w| w.to_vec()).collect(),
+        ));
+        let leds = MockLeds::new(hw.config.leds.leds_in_line);
+        let renders = leds.renders();
+        hw.leds = Some(Box::new(leds));
+
+        let mut events = vec![];
+        let (cast, trace) = reading(&mut hw, &mut |event| events.push(event)).unwrap();
+
+        use Line::*;
+        assert_eq!(cast.hexagram().lines(), [Yang, Yin, Yang, Yin, Yin, Yang]);
+        // the same in the quick reading changes
+        assert_eq!(cast.related().lines(), [Yin, Yin, Yin, Yang, Yin, Yang]);
+        assert_eq!(cast.moving(), vec![1, 3, 4]);
+        assert_eq!(trace.lines.len(), 6);
+        assert_eq!(trace.related_lines.len(), 6);
+        assert!(trace.fallback.is_none());
+        assert_eq!(events.len(), 14);
+        assert!(!renders.lock().unwrap().is_empty());
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+    }
+
+    #[test]
+    fn fallback_keeps_the_window_which_failed() {
+        let mut hw = mock("fallback");
+        // the first related window is flat
+        let windows = [YANG, YIN, YANG, [500; 5]];
+        hw.sensor = Box::new(MockSensor::scripted(
+            windows.iter().map(|w| w.to_vec()).collect(),
+        ));
+
+        let (cast, trace) = reading(&mut hw, &mut |_| {}).unwrap();
+
+        let fallback = trace.fallback.unwrap();
+        assert_eq!(fallback.failed, fallback::Read::Related(1));
+        assert_eq!(trace.lines.len(), 3);
+        assert_eq!(trace.related_lines.len(), 1);
+        // lines already read keep their polarity
+        assert_eq!(
+            cast.hexagram().lines()[..3],
+            [Line::Yang, Line::Yin, Line::Yang]
+        );
+
+        hw.config.fallback.method = FallbackMethod::Off;
+        hw.sensor = Box::new(MockSensor::scripted(vec![vec![]]));
+        match reading(&mut hw, &mut |_| {}) {
+            Err(Stopped::NoData(reason)) => assert_eq!(reason, "no usable data for line 1"),
+            other => panic!("{:?}", other.map(|(cast, _)| cast)),
+        }
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+    }
+
+    #[test]
+    fn trigrams_fire_their_pins_and_effects() {
+        let mut hw = mock("react");
+        let pins = MockPins::new();
+        let pin_log = pins.log();
+        hw.pins = Box::new(pins);
+        let effects = MockEffects::new();
+        let effect_log = effects.log();
+        hw.effects = Box::new(effects);
+
+        let reaction = react(&mut hw, Trigram::Heaven, 6, 1, 2);
+        assert_eq!(reaction.actions, vec!["pin 5"]);
+        let reaction = react(&mut hw, Trigram::Mountain, 3, 4, 5);
+        assert_eq!(reaction.actions, vec!["pin 7", "mountain"]);
+        let reaction = react(&mut hw, Trigram::Sun, 6, 1, 2);
+        assert_eq!(reaction.actions, vec!["fire"]);
+        drop_pins(&mut hw);
+
+        // pin 7 is run by its script
+        assert_eq!(
+            *pin_log.lock().unwrap(),
+            vec![(5, true), (5, false), (20, false), (6, false)]
+        );
+        assert_eq!(
+            *effect_log.lock().unwrap(),
+            vec!["pin7", "mountain", "fire"]
+        );
+        // and it is a pump
+        assert_eq!(pump_usage(Path::new(&hw.config.pumps.usage_file)), Some(1));
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+    }
+
+    // stands for a pump script which doesn't come back in time
+    struct HungScript;
+
+    impl Effects for HungScript {
+        fn run(&mut self, _effect: &str) -> Result<(), String> {
+            std::thread::sleep(std::time::Duration::from_millis(1500));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn watchdog_lowers_the_pin_of_a_hung_script() {
+        let mut hw = mock("hung");
+        let forced = MockPins::new();
+        let forced_log = forced.log();
+        let mut config = config::Watchdog::default();
+        config.pins.insert("7".to_string(), 1);
+        let watchdog = Watchdog::start(&config, Box::new(forced), Subscribers::default());
+        hw.pins = Box::new(WatchedPins::new(Box::new(MockPins::new()), watchdog));
+        hw.effects = Box::new(HungScript);
+
+        pin_on(&mut hw, 7);
+
+        // forced low while the script still ran
+        assert_eq!(*forced_log.lock().unwrap(), vec![(7, false)]);
+        let _ = fs::remove_file(&hw.config.pumps.usage_file);
+    }
+
+    #[test]
+    fn pumps_count_and_reset() {
+        let hw = mock("pumps");
+        let file = Path::new(&hw.config.pumps.usage_file);
+        assert_eq!(pump_usage(file), None);
+        check_the_pumps(file, 2);
+        assert_eq!(pump_usage(file), Some(1));
+        check_the_pumps(file, 2);
+        assert_eq!(pump_usage(file), Some(2));
+        // refilled
+        check_the_pumps(file, 2);
+        assert_eq!(pump_usage(file), Some(0));
+        check_the_pumps(file, 2);
+        assert_eq!(pump_usage(file), Some(1));
+        let _ = fs::remove_file(file);
+    }
+}